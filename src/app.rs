@@ -1,30 +1,65 @@
-use std::fs::read_dir;
+use std::collections::HashSet;
 use std::io;
+use std::mem;
 use std::path::PathBuf;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::time::{Duration, Instant};
 
 use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
 
+use ratatui::layout::Position;
 use ratatui::prelude::{Buffer, Constraint, Direction, Layout, Line, Rect, StatefulWidget, Text};
-use ratatui::style::Modifier;
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::symbols::Marker;
 use ratatui::text::Span;
-use ratatui::widgets::{Block, Paragraph, Widget};
-use ratatui::{DefaultTerminal, Frame, style::Stylize, symbols::border};
+use ratatui::widgets::canvas::{Canvas, Line as CanvasLine};
+use ratatui::widgets::{Block, Clear, LineGauge, Paragraph, Widget};
+use ratatui::{DefaultTerminal, Frame, style::Stylize, symbols, symbols::border};
 
 use ratatui_image::{StatefulImage, picker::Picker, protocol::StatefulProtocol};
 
-use image;
-use image::imageops::FilterType;
+use image::DynamicImage;
 
 use crossterm::event;
-use crossterm::event::{Event, KeyCode, KeyEvent};
+use crossterm::event::{
+    Event, KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+};
 
+use crate::browser::{Grouping, LibraryBrowser};
 use crate::expand_tilde;
 use crate::files::FileSelector;
+use crate::library::{Library, ScanProgress};
+use crate::loader::{
+    LibraryUpdate, Placement, PlacementStep, PlaylistPlacement, SongLoad, SongLoader,
+    list_tree_in_background, load_cover_in_background, poll, rescan_dirs_in_background,
+    scan_library_in_background,
+};
 use crate::player::{MetadataType, Player, PlayerCommand};
-use crate::song::Song;
+use crate::playlist::{PlaylistFormat, unused_file_name, write_playlist};
+use crate::plays::{ListeningTime, PlayCounts};
+use crate::search::{Search, SearchItem};
+use crate::smart::SmartPlaylist;
+use crate::state::SavedState;
+use crate::visualizer::{Meters, Spectrogram, VisualizerMode, heat_color, oscilloscope};
+use crate::watcher::FileWatcher;
 
 const EQ_POS_CHAR: char = '■';
 const EQ_NEG_CHAR: char = ' ';
+const EQ_PEAK_CHAR: char = '▔';
+
+// Longest the interface waits for input before drawing again
+const FRAME_TIME: Duration = Duration::from_millis(200);
+const VISUALIZER_FRAME_TIME: Duration = Duration::from_millis(40);
+
+// Copying an album in changes its files many times over, the library waits for it to be done
+const LIBRARY_CHANGE_DELAY: Duration = Duration::from_secs(2);
+
+// How long messages stay in the controls block
+const STATUS_DURATION: Duration = Duration::from_secs(6);
+
+// How much of a song has to be heard for it to count as played
+const PLAY_COUNT_FRACTION: f64 = 0.5;
 
 #[derive(PartialEq)]
 enum DisplayMode {
@@ -32,6 +67,36 @@ enum DisplayMode {
     Queue,
     CurrentTrack,
     FileSelection,
+    Library,
+    SmartPlaylists,
+}
+
+// A message for the controls block, which goes away after a while
+struct Status {
+    message: Option<String>,
+    set_at: Instant,
+}
+
+impl Status {
+    fn new() -> Self {
+        Self {
+            message: None,
+            set_at: Instant::now(),
+        }
+    }
+
+    fn set(&mut self, message: String) -> () {
+        self.message = Some(message);
+        self.set_at = Instant::now();
+    }
+
+    fn message(&self) -> Option<&str> {
+        if self.set_at.elapsed() >= STATUS_DURATION {
+            return None;
+        }
+
+        return self.message.as_deref();
+    }
 }
 
 pub struct App {
@@ -42,53 +107,162 @@ pub struct App {
 
     player: Player,
     file_selector: FileSelector,
-
-    files_queue: Option<Vec<PathBuf>>,
+    library_browser: LibraryBrowser,
+    // Set while the library is being scanned
+    library_scan: Option<Receiver<LibraryUpdate>>,
+    scan_progress: Option<ScanProgress>,
+
+    // Tells about files changing while gigr runs, `None` where they can't be watched
+    watcher: Option<FileWatcher>,
+    // How many times the queue had changed when its directories were last watched
+    watched_queue_changes: Option<u64>,
+    // Library directories the watcher saw change, scanned again once no other scan is running
+    changed_library_dirs: Vec<PathBuf>,
+    last_library_change: Instant,
+
+    // Open while the search prompt is shown
+    search: Option<Search>,
+    // Set while the files to search are being listed, for when there's no library
+    search_listing: Option<Receiver<Result<Vec<SearchItem>>>>,
+
+    // Reads the tags of whatever gets added to the queue
+    song_loader: SongLoader,
+    // Where the songs of the playlist that is being loaded go
+    playlist_placement: Option<PlaylistPlacement>,
+
+    play_counts: PlayCounts,
+    listening_time: ListeningTime,
+    // Set once the current song was played far enough to count
+    counted_play: bool,
+
+    smart_playlists: Vec<SmartPlaylist>,
+    smart_playlist_cursor: usize,
+
+    queue_cursor: usize,
 
     album_art: Option<StatefulProtocol>,
+    // Set while the current song's cover art is being loaded
+    album_art_receiver: Option<Receiver<Result<Option<DynamicImage>>>>,
+    // Asking the terminal about its image support takes a while, so it only happens once
+    picker: Picker,
+
+    progress_bar_area: Rect,
+
+    visualizer_mode: VisualizerMode,
+    // History of the spectrogram and the state of the meters, kept between frames
+    spectrogram: Spectrogram,
+    meters: Meters,
+
+    // Shown in the controls block, e.g. when a playlist had entries that couldn't be found
+    status: Status,
 }
 
 impl App {
-    pub fn new() -> Self {
-        //let album_art_image = App::load_album_cover(expand_tilde("~/Music"));
+    // Rescanning reads the tags of every file in the library again, not just the changed ones
+    pub fn new(rescan: bool) -> Result<Self> {
+        let mut player = Player::new()?;
+        if let Some(state) = SavedState::load() {
+            player.restore_state(state);
+        }
 
-        Self {
+        let mut status = Status::new();
+        let watcher = match FileWatcher::new() {
+            Ok(watcher) => Some(watcher),
+            Err(error) => {
+                status.set(format!("Couldn't watch for file changes: {}", error));
+                None
+            }
+        };
+
+        let smart_playlists = SmartPlaylist::load_all().unwrap_or_else(|error| {
+            status.set(format!("Couldn't load the smart playlists: {}", error));
+            Vec::new()
+        });
+
+        // Terminals that don't answer get images drawn with half blocks
+        let picker = Picker::from_query_stdio().unwrap_or_else(|_| Picker::from_fontsize((8, 16)));
+
+        Ok(Self {
             exit: false,
             queued_command: None,
 
             display_mode: DisplayMode::Title,
 
-            player: Player::new(),
-            file_selector: FileSelector::new(expand_tilde("~/Music")),
+            player,
+            file_selector: FileSelector::new(expand_tilde("~/Music"))?,
+            // Filled in once the index is loaded
+            library_browser: LibraryBrowser::new(Library::new(Library::configured_roots())),
+            library_scan: Some(scan_library_in_background(rescan)),
+            scan_progress: None,
+
+            watcher,
+            watched_queue_changes: None,
+            changed_library_dirs: Vec::new(),
+            last_library_change: Instant::now(),
+
+            search: None,
+            search_listing: None,
+
+            song_loader: SongLoader::new(),
+            playlist_placement: None,
 
-            files_queue: None,
+            play_counts: PlayCounts::load(),
+            listening_time: ListeningTime::new(),
+            counted_play: false,
+
+            smart_playlists,
+            smart_playlist_cursor: 0,
+
+            queue_cursor: 0,
 
             album_art: None,
-        }
+            album_art_receiver: None,
+            picker,
+
+            progress_bar_area: Rect::default(),
+
+            visualizer_mode: VisualizerMode::Bars,
+            spectrogram: Spectrogram::new(),
+            meters: Meters::new(),
+
+            status,
+        })
     }
 
     /// runs the application's main loop until the user quits
     pub fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
         while !self.exit {
-            match &self.files_queue {
-                Some(entries) => {
-                    for path in entries {
-                        if path.is_file() {
-                            if let Some(ext) = path.extension() {
-                                if ext == "mp3" || ext == "flac" || ext == "wav" {
-                                    let song = Song::new(&path.to_str().unwrap());
-                                    self.player.add_to_queue(song);
-                                }
-                            }
+            while let Some(load) = self.song_loader.try_next() {
+                match load {
+                    SongLoad::Loaded(song, placement) => {
+                        self.player.add_to_queue(*song);
+
+                        let step = self
+                            .playlist_placement
+                            .get_or_insert_with(|| PlaylistPlacement::new(placement))
+                            .place(self.player.play_order(), self.player.player_index);
+                        match step {
+                            PlacementStep::Stay => {}
+                            PlacementStep::Move(from, to) => self.player.move_in_queue(from, to),
+                            PlacementStep::PlayNow(position) => self.player.play_now(position),
+                        }
+                    }
+                    SongLoad::Finished(skipped) => {
+                        self.playlist_placement = None;
+
+                        if let Some(first) = skipped.first() {
+                            self.status.set(format!(
+                                "Skipped {} entries, like {}",
+                                skipped.len(),
+                                first
+                            ));
                         }
                     }
                 }
-                None => {}
             }
-            self.files_queue = None;
 
-            match &self.queued_command {
-                Some(command) => match command {
+            if let Some(command) = &self.queued_command {
+                match command {
                     PlayerCommand::Prev => {
                         self.player.return_last_song();
                     }
@@ -101,33 +275,253 @@ impl App {
                     PlayerCommand::PlayPause => {
                         self.player.play_pause();
                     }
-                },
-                None => {}
+                    PlayerCommand::Seek(offset) => {
+                        self.player.seek(*offset);
+                    }
+                    PlayerCommand::SeekTo(fraction) => {
+                        self.player.seek_to_fraction(*fraction);
+                    }
+                    PlayerCommand::CycleCrossfade => {
+                        self.player.cycle_crossfade();
+                    }
+                    PlayerCommand::CycleReplayGain => {
+                        self.player.cycle_replay_gain_mode();
+                    }
+                    PlayerCommand::CycleRepeat => {
+                        self.player.cycle_repeat_mode();
+                    }
+                    PlayerCommand::ToggleStopAfterCurrent => {
+                        self.player.toggle_stop_after_current();
+                    }
+                    PlayerCommand::ToggleShuffle => {
+                        self.player.toggle_shuffle();
+                    }
+                    PlayerCommand::MoveInQueue(from, to) => {
+                        self.player.move_in_queue(*from, *to);
+                    }
+                    PlayerCommand::PlayNext(position) => {
+                        self.player.play_next(*position);
+                    }
+                    PlayerCommand::PlayNow(position) => {
+                        self.player.play_now(*position);
+                    }
+                    PlayerCommand::RemoveFromQueue(position) => {
+                        self.player.remove_from_queue(*position);
+                    }
+                    PlayerCommand::ClearQueue => {
+                        self.player.clear_queue();
+                    }
+                    PlayerCommand::CycleSpectrumFloor => {
+                        self.player.spectrum().cycle_floor();
+                    }
+                    PlayerCommand::CycleSpectrumWindow => {
+                        self.player.spectrum().cycle_window();
+                    }
+                }
             }
 
             self.queued_command = None;
+            self.queue_cursor = self
+                .queue_cursor
+                .min(self.player.queue().len().saturating_sub(1));
+
             let update: bool = self.player.update();
 
+            // A song counts as played once half of it was heard, skipping through it doesn't count
             if update {
-                let mut img_path = self.player.current_song().unwrap().file_path_as_path();
-                img_path.pop();
-                self.album_art = App::load_album_cover(img_path);
+                self.listening_time
+                    .restart(self.player.position(), Instant::now());
+                self.counted_play = false;
+            } else {
+                self.listening_time
+                    .update(self.player.position(), Instant::now());
+            }
+            // Songs of unknown length can't be half heard
+            if !self.counted_play
+                && let Some(song) = self.player.current_song()
+                && !song.duration.is_zero()
+                && self.listening_time.heard() >= song.duration.mul_f64(PLAY_COUNT_FRACTION)
+            {
+                self.play_counts
+                    .record(&song.file_path_clone(), song.cue_track.as_ref());
+                self.counted_play = true;
+
+                // Saved right away, so that the plays survive gigr getting killed
+                if let Err(error) = self.play_counts.save() {
+                    self.status
+                        .set(format!("Couldn't save the play counts: {}", error));
+                }
+            }
+
+            if let Some(error) = self.player.take_error() {
+                self.status.set(error);
+            }
+
+            // Replacing the receiver abandons the cover of the previous song if it isn't there yet
+            if update && let Some(song) = self.player.current_song() {
+                self.album_art = None;
+                self.album_art_receiver = Some(load_cover_in_background(song.file_path_as_path()));
+            }
+
+            if let Some(receiver) = &self.album_art_receiver
+                && let Some(result) = poll(receiver)
+            {
+                self.album_art_receiver = None;
+                match result {
+                    Ok(cover) => {
+                        self.album_art = cover.map(|image| self.picker.new_resize_protocol(image));
+                    }
+                    Err(error) => {
+                        self.status
+                            .set(format!("Couldn't load the album art: {}", error));
+                    }
+                }
+            }
+
+            while let Some(receiver) = &self.library_scan {
+                match receiver.try_recv() {
+                    Ok(LibraryUpdate::Loaded(library) | LibraryUpdate::Scanned(library)) => {
+                        self.library_browser.set_library(library);
+                    }
+                    Ok(LibraryUpdate::Progress(progress)) => self.scan_progress = Some(progress),
+                    Ok(LibraryUpdate::Failed(error)) => {
+                        self.status
+                            .set(format!("Couldn't save the library: {}", error));
+                    }
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        self.library_scan = None;
+                        self.scan_progress = None;
+                    }
+                }
+            }
+
+            self.watch_files();
+
+            if let Some(receiver) = &self.search_listing
+                && let Some(result) = poll(receiver)
+            {
+                self.search_listing = None;
+                match (result, &mut self.search) {
+                    (Ok(items), Some(search)) => search.set_items(items),
+                    (Ok(_), None) => {}
+                    (Err(error), _) => {
+                        self.status
+                            .set(format!("Couldn't list the files: {}", error));
+                    }
+                }
+            }
+
+            if self.library_scan.is_none()
+                && !self.changed_library_dirs.is_empty()
+                && self.last_library_change.elapsed() >= LIBRARY_CHANGE_DELAY
+            {
+                self.library_scan = Some(rescan_dirs_in_background(
+                    self.library_browser.library().clone(),
+                    mem::take(&mut self.changed_library_dirs),
+                ));
             }
 
             terminal.draw(|frame| self.draw(frame))?;
             self.handle_events()?;
         }
+
+        // Neither is lost because the other couldn't be saved
+        let state_saved = self.player.saved_state().save();
+        let plays_saved = self.play_counts.save();
+
+        match (state_saved, plays_saved) {
+            (Err(state_error), Err(plays_error)) => {
+                return Err(eyre!(
+                    "couldn't save the playback state ({}) or the play counts ({})",
+                    state_error,
+                    plays_error
+                ));
+            }
+            (Err(error), _) => return Err(error).wrap_err("couldn't save the playback state"),
+            (_, Err(error)) => return Err(error).wrap_err("couldn't save the play counts"),
+            (Ok(()), Ok(())) => {}
+        }
+
         Ok(())
     }
 
+    // Keeps the file selector, the library and the queue in step with the files on disk
+    fn watch_files(&mut self) -> () {
+        let Some(watcher) = &mut self.watcher else {
+            return;
+        };
+
+        let roots = &self.library_browser.library().roots;
+        let running_path = self.file_selector.running_path();
+
+        if let Err(error) = watcher.watch_directory(running_path) {
+            self.status
+                .set(format!("Couldn't watch for file changes: {}", error));
+        }
+
+        watcher.watch_library(roots);
+        if let Some(error) = watcher.library_error() {
+            self.status
+                .set(format!("Couldn't watch the library: {}", error));
+        }
+
+        let queue_changes = self.player.queue_changes();
+        if self.watched_queue_changes != Some(queue_changes) {
+            let queue_dirs: HashSet<PathBuf> = self
+                .player
+                .queue()
+                .iter()
+                .filter_map(|song| song.file_path_as_path().parent().map(PathBuf::from))
+                .collect();
+            watcher.watch_queue(queue_dirs);
+            self.watched_queue_changes = Some(queue_changes);
+        }
+
+        let changed_paths = watcher.changed_paths();
+        if changed_paths.is_empty() {
+            return;
+        }
+
+        if changed_paths
+            .iter()
+            .any(|path| path.parent() == Some(running_path))
+        {
+            self.file_selector.refresh();
+        }
+
+        self.player.check_missing_files(&changed_paths);
+
+        // A file that changed is scanned along with the rest of its directory, which also
+        // covers directories that were created or removed
+        let changed_dirs: Vec<PathBuf> = changed_paths
+            .iter()
+            .filter_map(|path| path.parent())
+            .filter(|dir_path| roots.iter().any(|root| dir_path.starts_with(root)))
+            .map(PathBuf::from)
+            .collect();
+        if !changed_dirs.is_empty() {
+            self.changed_library_dirs.extend(changed_dirs);
+            self.last_library_change = Instant::now();
+        }
+    }
+
     fn draw(&mut self, frame: &mut Frame) {
         frame.render_widget(self, frame.area());
     }
 
     fn handle_events(&mut self) -> io::Result<()> {
-        if crossterm::event::poll(std::time::Duration::from_millis(200))? {
-            if let Event::Key(key) = event::read()? {
-                self.handle_key_event(key);
+        // The visualizer needs a higher frame rate than the rest of the interface
+        let timeout = match self.display_mode {
+            DisplayMode::CurrentTrack => VISUALIZER_FRAME_TIME,
+            _ => FRAME_TIME,
+        };
+
+        if crossterm::event::poll(timeout)? {
+            match event::read()? {
+                Event::Key(key) => self.handle_key_event(key),
+                Event::Mouse(mouse) => self.handle_mouse_event(mouse),
+                _ => {}
             }
         }
 
@@ -135,6 +529,12 @@ impl App {
     }
 
     fn handle_key_event(&mut self, key_event: KeyEvent) {
+        // Everything typed while the search prompt is open goes to the prompt
+        if self.search.is_some() {
+            self.handle_search_key_event(key_event);
+            return;
+        }
+
         match key_event.code {
             // PLAYER EVENTS
             KeyCode::Char('q') => self.exit(),
@@ -143,12 +543,28 @@ impl App {
             KeyCode::Char('k') => self.queued_command = Some(PlayerCommand::VolumeChange(0.05)),
             KeyCode::Char('l') => self.queued_command = Some(PlayerCommand::Skip),
             KeyCode::Char(' ') => self.queued_command = Some(PlayerCommand::PlayPause),
+            KeyCode::Char(',') => self.queued_command = Some(PlayerCommand::Seek(-5.0)),
+            KeyCode::Char('.') => self.queued_command = Some(PlayerCommand::Seek(5.0)),
+            KeyCode::Char('<') => self.queued_command = Some(PlayerCommand::Seek(-30.0)),
+            KeyCode::Char('>') => self.queued_command = Some(PlayerCommand::Seek(30.0)),
+            KeyCode::Char('r') => self.queued_command = Some(PlayerCommand::CycleRepeat),
+            KeyCode::Char('z') => self.queued_command = Some(PlayerCommand::ToggleShuffle),
+            KeyCode::Char('x') => self.queued_command = Some(PlayerCommand::ToggleStopAfterCurrent),
+            KeyCode::Char('c') => self.queued_command = Some(PlayerCommand::CycleCrossfade),
+            KeyCode::Char('g') => self.queued_command = Some(PlayerCommand::CycleReplayGain),
+            KeyCode::Char(digit @ '0'..='9') => {
+                let percentage = digit.to_digit(10).unwrap() as f32 * 10.0;
+                self.queued_command = Some(PlayerCommand::SeekTo(percentage / 100.0));
+            }
 
             // UI
             // DISPLAY MODE SELECTION
             KeyCode::Char('p') => self.display_mode = DisplayMode::CurrentTrack,
             KeyCode::Char('o') => self.display_mode = DisplayMode::Queue,
             KeyCode::Char('i') => self.display_mode = DisplayMode::FileSelection,
+            KeyCode::Char('b') => self.display_mode = DisplayMode::Library,
+            KeyCode::Char('m') => self.display_mode = DisplayMode::SmartPlaylists,
+            KeyCode::Char('/') => self.open_search(),
 
             _ => self.queued_command = None,
         }
@@ -158,43 +574,479 @@ impl App {
             match key_event.code {
                 KeyCode::Char('s') => self.file_selector.move_down(),
                 KeyCode::Char('d') => self.file_selector.move_up(),
-                KeyCode::Char('f') => self.file_selector.move_forwards(),
-                KeyCode::Char('a') => self.file_selector.move_back(),
-                KeyCode::Enter => self.files_queue = self.file_selector.queue_selection(),
+                KeyCode::Char('f') => {
+                    if let Err(error) = self.file_selector.move_forwards() {
+                        self.status
+                            .set(format!("Couldn't open the directory: {}", error));
+                    }
+                }
+                KeyCode::Char('a') => {
+                    if let Err(error) = self.file_selector.move_back() {
+                        self.status
+                            .set(format!("Couldn't open the directory: {}", error));
+                    }
+                }
+                KeyCode::Enter => match self.file_selector.queue_selection() {
+                    Ok(playlist) => self.song_loader.load(playlist, Placement::End),
+                    Err(error) => self
+                        .status
+                        .set(format!("Couldn't add to the queue: {}", error)),
+                },
+
+                _ => {}
+            }
+        }
+
+        // LIBRARY
+        if self.display_mode == DisplayMode::Library {
+            match key_event.code {
+                KeyCode::Char('s') => self.library_browser.move_down(),
+                KeyCode::Char('d') => self.library_browser.move_up(),
+                KeyCode::Char('f') => self.library_browser.move_forwards(),
+                KeyCode::Char('a') => self.library_browser.move_back(),
+                KeyCode::Char('t') => self.library_browser.toggle_grouping(),
+                KeyCode::Char('u') if self.library_scan.is_none() => {
+                    self.library_scan = Some(scan_library_in_background(false));
+                }
+                KeyCode::Char('U') if self.library_scan.is_none() => {
+                    self.library_scan = Some(scan_library_in_background(true));
+                }
+                KeyCode::Enter => {
+                    let playlist = self.library_browser.queue_selection();
+                    self.song_loader.load(playlist, Placement::End);
+                }
+
+                _ => {}
+            }
+        }
+
+        // SMART PLAYLISTS
+        if self.display_mode == DisplayMode::SmartPlaylists {
+            match key_event.code {
+                KeyCode::Char('s')
+                    if self.smart_playlist_cursor + 1 < self.smart_playlists.len() =>
+                {
+                    self.smart_playlist_cursor += 1;
+                }
+                KeyCode::Char('d') => {
+                    self.smart_playlist_cursor = self.smart_playlist_cursor.saturating_sub(1)
+                }
+                KeyCode::Char('u') => self.reload_smart_playlists(),
+                KeyCode::Enter => self.queue_smart_playlist(),
+
+                _ => {}
+            }
+        }
+
+        // TRACK INFO
+        if self.display_mode == DisplayMode::CurrentTrack {
+            match key_event.code {
+                KeyCode::Char('f') => self.queued_command = Some(PlayerCommand::CycleSpectrumFloor),
+                KeyCode::Char('w') => {
+                    self.queued_command = Some(PlayerCommand::CycleSpectrumWindow)
+                }
+                KeyCode::Char('v') => self.visualizer_mode = self.visualizer_mode.next(),
+
+                _ => {}
+            }
+        }
+
+        // QUEUE EDITING
+        if self.display_mode == DisplayMode::Queue {
+            let cursor = self.queue_cursor;
+
+            match key_event.code {
+                KeyCode::Char('s') => self.queue_cursor += 1,
+                KeyCode::Char('d') => self.queue_cursor = cursor.saturating_sub(1),
+                KeyCode::Char('K') if cursor > 0 => {
+                    self.queued_command = Some(PlayerCommand::MoveInQueue(cursor, cursor - 1));
+                    self.queue_cursor -= 1;
+                }
+                KeyCode::Char('J') if cursor + 1 < self.player.queue().len() => {
+                    self.queued_command = Some(PlayerCommand::MoveInQueue(cursor, cursor + 1));
+                    self.queue_cursor += 1;
+                }
+                KeyCode::Char('n') => self.queued_command = Some(PlayerCommand::PlayNext(cursor)),
+                KeyCode::Enter => self.queued_command = Some(PlayerCommand::PlayNow(cursor)),
+                KeyCode::Delete | KeyCode::Backspace => {
+                    self.queued_command = Some(PlayerCommand::RemoveFromQueue(cursor))
+                }
+                KeyCode::Char('C') => self.queued_command = Some(PlayerCommand::ClearQueue),
+                KeyCode::Char('w') => self.export_queue(PlaylistFormat::M3u),
+                KeyCode::Char('P') => self.export_queue(PlaylistFormat::Pls),
+                KeyCode::Char('X') => self.export_queue(PlaylistFormat::Xspf),
 
                 _ => {}
             }
         }
     }
 
-    fn load_album_cover(dir_path: PathBuf) -> Option<StatefulProtocol> {
-        let entries = read_dir(dir_path).unwrap().map(|res| res.unwrap());
+    // Writes the queue as a playlist into the directory open in the file selector
+    fn export_queue(&mut self, format: PlaylistFormat) -> () {
+        let path = unused_file_name(
+            self.file_selector.running_path(),
+            "gigr-queue",
+            format.extension(),
+        );
+
+        match write_playlist(&path, format, &self.player.songs_in_play_order()) {
+            Ok(left_out) => {
+                let mut message = format!("Saved the queue as {}", path.display());
+                // Playlists can only point at their whole file
+                if left_out > 0 {
+                    message.push_str(&format!(", CUE sheet tracks left out: {}", left_out));
+                }
+
+                self.status.set(message);
+                self.file_selector.refresh();
+            }
+            Err(error) => self
+                .status
+                .set(format!("Couldn't save the queue: {}", error)),
+        }
+    }
+
+    fn reload_smart_playlists(&mut self) -> () {
+        match SmartPlaylist::load_all() {
+            Ok(smart_playlists) => {
+                self.smart_playlist_cursor = self
+                    .smart_playlist_cursor
+                    .min(smart_playlists.len().saturating_sub(1));
+                self.smart_playlists = smart_playlists;
+            }
+            Err(error) => self
+                .status
+                .set(format!("Couldn't load the smart playlists: {}", error)),
+        }
+    }
+
+    // Adds every track of the library the selected playlist's query matches to the queue
+    fn queue_smart_playlist(&mut self) -> () {
+        let Some(smart_playlist) = self.smart_playlists.get(self.smart_playlist_cursor) else {
+            return;
+        };
+
+        match smart_playlist.tracks(self.library_browser.library(), &self.play_counts) {
+            Ok(playlist) if playlist.entries.is_empty() => {
+                self.status.set(format!(
+                    "Nothing in the library matches {}",
+                    smart_playlist.name
+                ));
+            }
+            Ok(playlist) => self.song_loader.load(playlist, Placement::End),
+            Err(error) => {
+                self.status
+                    .set(format!("Couldn't use {}: {}", smart_playlist.name, error));
+            }
+        }
+    }
+
+    // Searches the library, or the files below the directory open in the file selector
+    // when there's no library
+    fn open_search(&mut self) -> () {
+        let tracks = &self.library_browser.library().tracks;
+
+        match tracks.is_empty() {
+            false => {
+                let items = tracks.iter().map(SearchItem::from_track).collect();
+                self.search = Some(Search::new(items));
+            }
+            true => {
+                self.search = Some(Search::new(Vec::new()));
+                self.search_listing = Some(list_tree_in_background(
+                    self.file_selector.running_path().clone(),
+                ));
+            }
+        }
+    }
+
+    fn handle_search_key_event(&mut self, key_event: KeyEvent) -> () {
+        let Some(search) = &mut self.search else {
+            return;
+        };
+        let control = key_event.modifiers.contains(KeyModifiers::CONTROL);
+
+        let placement = match key_event.code {
+            KeyCode::Enter => Placement::End,
+            KeyCode::Char('n') if control => Placement::Next,
+            KeyCode::Char('p') if control => Placement::Now,
+            code => {
+                match code {
+                    KeyCode::Esc => {
+                        self.search = None;
+                        self.search_listing = None;
+                    }
+                    KeyCode::Up => search.move_up(),
+                    KeyCode::Down => search.move_down(),
+                    KeyCode::Backspace => search.pop(),
+                    KeyCode::Char(c) if !control => search.push(c),
+
+                    _ => {}
+                }
+                return;
+            }
+        };
+
+        if let Some(item) = search.selected_item() {
+            self.song_loader.load(item.playlist(), placement);
+        }
+    }
+
+    fn handle_mouse_event(&mut self, mouse_event: MouseEvent) {
+        if mouse_event.kind != MouseEventKind::Down(MouseButton::Left) {
+            return;
+        }
+
+        let area = self.progress_bar_area;
+        if area.contains(Position::new(mouse_event.column, mouse_event.row)) {
+            let fraction = (mouse_event.column - area.x) as f32 / area.width as f32;
+            self.queued_command = Some(PlayerCommand::SeekTo(fraction));
+        }
+    }
+
+    fn render_bars(&mut self, area: Rect, block: Block<'_>, buf: &mut Buffer) -> () {
+        let width: f32 = (area.width - 2) as f32;
+        let height: f32 = (area.height - 2) as f32;
 
-        for entry in entries {
-            let path = entry.path();
+        match self.player.eq_bands(width as i32) {
+            Some(frame) => {
+                let bands = &frame.levels;
+                let mut eq_chars: Vec<Line<'_>> = Vec::new();
 
-            if path.is_file() {
-                if let Some(ext) = path.extension() {
-                    if ext == "jpg" || ext == "png" {
-                        let picker = Picker::from_query_stdio().unwrap();
+                for _ in 0..2 {
+                    let mut line = String::from("");
+                    for _ in 0..bands.len() {
+                        line.push(EQ_NEG_CHAR);
+                    }
+                    let name_span = Line::from(vec![Span::raw(line.to_string()).blue()]);
+                    eq_chars.push(name_span);
+                }
 
-                        let dyn_img = image::ImageReader::open(path);
+                for i in 0..height.round() as isize - 3 {
+                    let mut line = String::from("");
+                    for j in 0..bands.len() {
+                        let element = *bands.get(j).unwrap();
+                        let peak = *frame.peaks.get(j).unwrap();
+                        let threshold = 1f32 - (i + 1) as f32 * 1f32 / height;
 
-                        match dyn_img {
-                            Ok(img) => {
-                                let image = picker.new_resize_protocol(
-                                    img.decode().unwrap().resize(600, 600, FilterType::Gaussian),
-                                );
-                                return Some(image);
-                            }
-                            _ => {}
+                        if element > threshold {
+                            line.push(EQ_POS_CHAR);
+                        } else if peak > threshold && peak <= threshold + 1f32 / height {
+                            // The peak marker sits in the row its level falls into
+                            line.push(EQ_PEAK_CHAR);
+                        } else {
+                            line.push(EQ_NEG_CHAR);
                         }
                     }
+                    let name_span = Line::from(vec![Span::raw(line.to_string()).blue()]);
+                    eq_chars.push(name_span);
+                }
+
+                let mut line = String::from("");
+                for _ in 0..bands.len() {
+                    line.push(EQ_POS_CHAR);
                 }
+                let name_span = Line::from(vec![Span::raw(line.to_string()).blue()]);
+                eq_chars.push(name_span);
+
+                Paragraph::new(eq_chars)
+                    .centered()
+                    .block(block)
+                    .render(area, buf);
             }
+            None => Self::render_no_audio(area, block, buf),
+        }
+    }
+
+    fn render_oscilloscope(&mut self, area: Rect, block: Block<'_>, buf: &mut Buffer) -> () {
+        let Some(samples) = self.player.latest_samples() else {
+            return Self::render_no_audio(area, block, buf);
+        };
+
+        // Braille characters have two dots side by side
+        let width = block.inner(area).width as usize * 2;
+        let points = oscilloscope(&samples, self.player.output_channels(), width);
+
+        Canvas::default()
+            .block(block)
+            .marker(Marker::Braille)
+            .x_bounds([0.0, points.len().saturating_sub(1) as f64])
+            .y_bounds([-1.0, 1.0])
+            .paint(|ctx| {
+                for (x, pair) in points.windows(2).enumerate() {
+                    let x = x as f64;
+                    ctx.draw(&CanvasLine::new(x, pair[0], x + 1.0, pair[1], Color::Blue));
+                }
+            })
+            .render(area, buf);
+    }
+
+    // Time runs from left to right with the newest column at the right edge,
+    // frequencies from the bottom up
+    fn render_spectrogram(&mut self, area: Rect, block: Block<'_>, buf: &mut Buffer) -> () {
+        let inner = block.inner(area);
+
+        match self.player.eq_bands(inner.height as i32) {
+            Some(frame) => self.spectrogram.push(frame.levels, inner.width as usize),
+            None => return Self::render_no_audio(area, block, buf),
         }
 
-        return None;
+        let lines: Vec<Line<'_>> = (0..inner.height as usize)
+            .rev()
+            .map(|band| {
+                let spans: Vec<Span<'_>> = self
+                    .spectrogram
+                    .columns()
+                    .iter()
+                    .map(|column| {
+                        let (r, g, b) = heat_color(column.get(band).copied().unwrap_or(0.0));
+                        Span::raw("█").fg(Color::Rgb(r, g, b))
+                    })
+                    .collect();
+                Line::from(spans)
+            })
+            .collect();
+
+        Paragraph::new(lines)
+            .right_aligned()
+            .block(block)
+            .render(area, buf);
+    }
+
+    fn render_meters(&mut self, area: Rect, block: Block<'_>, buf: &mut Buffer) -> () {
+        let Some(samples) = self.player.latest_samples() else {
+            return Self::render_no_audio(area, block, buf);
+        };
+
+        let channels = self.player.output_channels();
+        let readings = self.meters.update(&samples, channels);
+
+        // Room for the channel label in front of the bar
+        let bar_width = block.inner(area).width.saturating_sub(3) as usize;
+
+        let mut lines: Vec<Line<'_>> = Vec::new();
+        for (channel, reading) in readings.iter().enumerate() {
+            let label = match (channels, channel) {
+                (1, _) => "M".to_string(),
+                (2, 0) => "L".to_string(),
+                (2, _) => "R".to_string(),
+                (_, channel) => (channel + 1).to_string(),
+            };
+
+            let filled = (reading.level * bar_width as f32).round() as usize;
+            let peak = (reading.peak_level * bar_width as f32).round() as usize;
+
+            // The held peak is marked in the last cell it reaches
+            let bar: String = (0..bar_width)
+                .map(|i| match i {
+                    i if i < filled => EQ_POS_CHAR,
+                    i if i + 1 == peak => '|',
+                    _ => EQ_NEG_CHAR,
+                })
+                .collect();
+
+            lines.push(Line::from(vec![
+                Span::raw(format!(" {} ", label)).bold(),
+                Span::raw(bar).blue(),
+            ]));
+            lines.push(Line::from(format!(
+                "   RMS {}  Peak {}",
+                format_db(reading.rms_db),
+                format_db(reading.peak_db)
+            )));
+            lines.push(Line::from(""));
+        }
+
+        Paragraph::new(lines).block(block).render(area, buf);
+    }
+
+    fn render_search(&self, area: Rect, buf: &mut Buffer) -> () {
+        let Some(search) = &self.search else {
+            return;
+        };
+
+        let search_title = Line::from(" Search: ").bold();
+
+        let search_instructions = Line::from(vec![
+            " Down ".into(),
+            "<Down>".blue().bold(),
+            " Up ".into(),
+            "<Up>".blue().bold(),
+            " Add ".into(),
+            "<Enter>".blue().bold(),
+            " Play next ".into(),
+            "<Ctrl-n>".blue().bold(),
+            " Play now ".into(),
+            "<Ctrl-p>".blue().bold(),
+            " Close ".into(),
+            "<Esc> ".blue().bold(),
+        ]);
+
+        let found = match self.search_listing {
+            Some(_) => " Listing files... ".to_string(),
+            None => format!(" {} of {} ", search.result_count(), search.items().len()),
+        };
+
+        let search_block = Block::bordered()
+            .title(search_title.left_aligned())
+            .title(Line::from(found).right_aligned())
+            .title_bottom(search_instructions.centered())
+            .border_set(border::THICK);
+
+        let inner_area = search_block.inner(area);
+        Clear.render(area, buf);
+        search_block.render(area, buf);
+
+        let search_layout = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![Constraint::Length(2), Constraint::Min(0)])
+            .split(inner_area);
+
+        Paragraph::new(Line::from(vec![
+            "  / ".blue().bold(),
+            Span::raw(search.query()),
+            "█".into(),
+        ]))
+        .render(search_layout[0], buf);
+
+        // Only the results that fit get drawn, there can be tens of thousands
+        let selected_result = search.selected_result();
+        let visible = search_layout[1].height as usize;
+        let first = selected_result.saturating_sub(visible.saturating_sub(1));
+
+        let result_lines: Vec<Line<'_>> = search
+            .results()
+            .enumerate()
+            .skip(first)
+            .take(visible)
+            .map(|(n, item)| {
+                let mut title = Span::raw(format!("  {}", item.title));
+                if n == selected_result {
+                    title = title.blue();
+                }
+
+                let tags: Vec<&str> = [&item.artist, &item.album]
+                    .into_iter()
+                    .flatten()
+                    .map(|tag| tag.as_str())
+                    .collect();
+                let details = match tags.is_empty() {
+                    true => format!("  {}", item.path.display()),
+                    false => format!("  {}", tags.join(" - ")),
+                };
+
+                return Line::from(vec![title, details.dim()]);
+            })
+            .collect();
+
+        Paragraph::new(result_lines).render(search_layout[1], buf);
+    }
+
+    fn render_no_audio(area: Rect, block: Block<'_>, buf: &mut Buffer) -> () {
+        Paragraph::new(Line::from(vec![Span::raw("Nothing playing.")]))
+            .centered()
+            .block(block)
+            .render(area, buf);
     }
 
     fn exit(&mut self) {
@@ -202,16 +1054,21 @@ impl App {
     }
 }
 
-impl<'a> Widget for &mut App {
+impl Widget for &mut App {
     fn render(self, area: Rect, buf: &mut Buffer) {
         let volume: u8 = (self.player.volume() * 100.0).round() as u8;
         let song_title: String = self.player.get_metadata(MetadataType::Title);
         let song_album: String = self.player.get_metadata(MetadataType::Album);
         let song_artist: String = self.player.get_metadata(MetadataType::TrackArtist);
         let playing: bool = self.player.playing();
-        let queue_len: usize = self.player.queue().len();
         let playback_time = self.player.playback_time();
         let total_time = self.player.total_time();
+        let stopped: bool = self.player.stopped();
+        let repeat_mode = self.player.repeat_mode();
+        let stop_after_current: bool = self.player.stop_after_current();
+        let shuffled: bool = self.player.shuffled();
+        let crossfade = self.player.crossfade();
+        let replay_gain_mode = self.player.replay_gain_mode();
 
         let layout = Layout::default()
             .direction(Direction::Vertical)
@@ -232,18 +1089,32 @@ impl<'a> Widget for &mut App {
             " Queue View ".into(),
             "<o>".blue().bold(),
             " Now Playing View ".into(),
-            "<p> ".blue().bold(),
+            "<p>".blue().bold(),
+            " Library ".into(),
+            "<b>".blue().bold(),
+            " Smart Playlists ".into(),
+            "<m>".blue().bold(),
+            " Search ".into(),
+            "</> ".blue().bold(),
         ]);
 
-        let np_block = Block::bordered()
+        let mut np_block = Block::bordered()
             .title(np_title.left_aligned())
             .title_bottom(mode_instructions.centered())
             .border_set(border::THICK);
 
-        let np_counter_text = Text::from(vec![Line::from(vec![Span::raw(format!(
-            "   {}",
-            song_title
-        ))])]);
+        if self.song_loader.loading() {
+            np_block =
+                np_block.title(Line::from(" Adding to the queue... ".yellow()).right_aligned());
+        }
+
+        // The next track is shown as loading until it's been opened
+        let np_counter_text = Text::from(vec![Line::from(vec![Span::raw(
+            match self.player.loading() {
+                true => "   Loading...".to_string(),
+                false => format!("   {}", song_title),
+            },
+        )])]);
 
         let np_playback_time = Text::from(vec![Line::from(vec![
             match playback_time.1 < 10 {
@@ -256,15 +1127,35 @@ impl<'a> Widget for &mut App {
             },
         ])]);
 
+        let np_inner_area = np_block.inner(layout[0]);
+        np_block.render(layout[0], buf);
+
+        let np_layout = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints(vec![
+                Constraint::Percentage(40),
+                Constraint::Min(0),
+                Constraint::Length(np_playback_time.width() as u16 + 1),
+            ])
+            .split(np_inner_area);
+
         Paragraph::new(np_counter_text)
             .left_aligned()
-            .block(np_block.clone())
-            .render(layout[0], buf);
+            .render(np_layout[0], buf);
+
+        // Remembered so that mouse clicks on the bar can be turned into seeks
+        self.progress_bar_area = np_layout[1];
+
+        LineGauge::default()
+            .ratio(self.player.progress())
+            .label("")
+            .line_set(symbols::line::THICK)
+            .filled_style(Style::new().blue())
+            .render(np_layout[1], buf);
 
         Paragraph::new(np_playback_time)
             .right_aligned()
-            .block(np_block)
-            .render(layout[0], buf);
+            .render(np_layout[2], buf);
 
         match self.display_mode {
             DisplayMode::Title => {
@@ -311,23 +1202,79 @@ impl<'a> Widget for &mut App {
                     .render(layout[1], buf);
             }
             DisplayMode::Queue => {
-                let trck_title = Line::from(" Next up: ".bold());
+                let trck_title = Line::from(" Queue: ".bold());
+
+                let queue_instructions = Line::from(vec![
+                    " Down ".into(),
+                    "<s>".blue().bold(),
+                    " Up ".into(),
+                    "<d>".blue().bold(),
+                    " Move down ".into(),
+                    "<J>".blue().bold(),
+                    " Move up ".into(),
+                    "<K>".blue().bold(),
+                    " Play next ".into(),
+                    "<n>".blue().bold(),
+                    " Play now ".into(),
+                    "<Enter>".blue().bold(),
+                    " Remove ".into(),
+                    "<Del>".blue().bold(),
+                    " Clear ".into(),
+                    "<C>".blue().bold(),
+                    " Save as M3U/PLS/XSPF ".into(),
+                    "<w/P/X> ".blue().bold(),
+                ]);
 
                 let mut track_lines: Vec<Line<'_>> = Vec::new();
 
-                for n in self.player.player_index..queue_len {
-                    let song = self.player.queue().get(n).unwrap();
-                    let span = Line::from(vec![Span::raw(format!("  {}", song.title_clone()))]);
-                    track_lines.push(span);
+                for (n, song) in self.player.songs_in_play_order().iter().enumerate() {
+                    let is_current = n + 1 == self.player.player_index;
+
+                    let mut span = Span::raw(format!(
+                        " {} {}{}",
+                        if is_current { ">" } else { " " },
+                        song.title_clone(),
+                        match self.player.file_missing(song) {
+                            true => " (file deleted)",
+                            false => "",
+                        }
+                    ));
+
+                    if self.player.file_missing(song) {
+                        span = span.crossed_out();
+                    }
+
+                    // Tracks before the current one were already played
+                    if n + 1 < self.player.player_index {
+                        span = span.dim();
+                    }
+
+                    if is_current {
+                        span = span.add_modifier(Modifier::BOLD);
+                    }
+
+                    if n == self.queue_cursor {
+                        span = span.blue();
+                    }
+
+                    track_lines.push(Line::from(vec![span]));
                 }
 
                 let trck_block = Block::bordered()
                     .title(trck_title.left_aligned())
-                    //.title_bottom(instructions.centered())
+                    .title_bottom(queue_instructions.centered())
                     .border_set(border::THICK);
 
+                let scroll: isize = self.queue_cursor as isize - 1;
+
                 Paragraph::new(track_lines)
                     .left_aligned()
+                    .scroll((
+                        (scroll * (scroll.is_positive() as isize))
+                            .try_into()
+                            .unwrap(),
+                        0,
+                    ))
                     .block(trck_block)
                     .render(layout[1], buf);
             }
@@ -349,16 +1296,18 @@ impl<'a> Widget for &mut App {
                     .border_set(border::THICK);
 
                 match &self.album_art {
+                    None if self.album_art_receiver.is_some() => {
+                        Paragraph::new(Line::from("Loading cover art..."))
+                            .centered()
+                            .block(album_art_block)
+                            .render(current_layout[1], buf);
+                    }
                     Some(_) => {
                         let album_art_inner_area = album_art_block.inner(current_layout[1]);
 
                         album_art_block.render(current_layout[1], buf);
 
-                        image.render(
-                            album_art_inner_area,
-                            buf,
-                            &mut self.album_art.as_mut().unwrap(),
-                        );
+                        image.render(album_art_inner_area, buf, self.album_art.as_mut().unwrap());
                     }
                     None => {
                         Paragraph::new(Line::from("No cover art."))
@@ -370,7 +1319,7 @@ impl<'a> Widget for &mut App {
 
                 let current_layout_info = Layout::default()
                     .direction(Direction::Vertical)
-                    .constraints(vec![Constraint::Length(7), Constraint::Min(0)])
+                    .constraints(vec![Constraint::Length(14), Constraint::Min(0)])
                     .split(current_layout[0]);
 
                 let track_info_title = Line::from(" Track info ");
@@ -382,18 +1331,29 @@ impl<'a> Widget for &mut App {
                 let name_span = Line::from(vec![Span::raw(format!("Title: {}", song_title))]);
                 track_info_lines.push(name_span);
 
-                let blank_line = Line::from(vec![Span::raw(" ")]);
-                track_info_lines.push(blank_line);
-
                 let artist_span = Line::from(vec![Span::raw(format!("Artist: {}", song_artist))]);
                 track_info_lines.push(artist_span);
 
-                let blank_line = Line::from(vec![Span::raw(" ")]);
-                track_info_lines.push(blank_line);
-
                 let album_span = Line::from(vec![Span::raw(format!("Album: {}", song_album))]);
                 track_info_lines.push(album_span);
 
+                let details = [
+                    ("Album artist", MetadataType::AlbumArtist),
+                    ("Composer", MetadataType::Composer),
+                    ("Track", MetadataType::TrackNumber),
+                    ("Disc", MetadataType::DiscNumber),
+                    ("Date", MetadataType::Date),
+                    ("Genre", MetadataType::Genre),
+                    ("Length", MetadataType::Duration),
+                    ("Format", MetadataType::Format),
+                    ("Comment", MetadataType::Comment),
+                ];
+                for (label, metadata_type) in details {
+                    let value = self.player.get_metadata(metadata_type);
+                    track_info_lines
+                        .push(Line::from(vec![Span::raw(format!("{}: {}", label, value))]));
+                }
+
                 // END OF LINES IN TRACK INFO
 
                 let track_info_block = Block::bordered()
@@ -406,60 +1366,49 @@ impl<'a> Widget for &mut App {
                     .block(track_info_block)
                     .render(current_layout_info[0], buf);
 
-                // EQ
+                // VISUALIZER
+
+                let spectrum = self.player.spectrum();
+                let mut visualizer_title = vec![format!(" {:?} ", self.visualizer_mode).into()];
+                if matches!(
+                    self.visualizer_mode,
+                    VisualizerMode::Bars | VisualizerMode::Spectrogram
+                ) {
+                    visualizer_title.push(
+                        format!(
+                            "({:?} window, {} dB floor) ",
+                            spectrum.window(),
+                            spectrum.floor_db()
+                        )
+                        .into(),
+                    );
+                    visualizer_title.extend([
+                        " Floor ".into(),
+                        "<f>".blue().bold(),
+                        " Window ".into(),
+                        "<w>".blue().bold(),
+                    ]);
+                }
+                visualizer_title.extend([" Mode ".into(), "<v> ".blue().bold()]);
 
-                let track_eq_block = Block::bordered()
+                let visualizer_block = Block::bordered()
                     //.title(curr_trck_dis_title.left_aligned())
-                    .title_bottom(Line::from(" EQ ").centered())
+                    .title_bottom(Line::from(visualizer_title).centered())
                     .border_set(border::THICK);
 
-                let width: f32 = (current_layout_info[1].width - 2) as f32;
-                let height: f32 = (current_layout_info[1].height - 2) as f32;
-
-                match self.player.eq_bands(width as i32) {
-                    Some(bands) => {
-                        let mut eq_chars: Vec<Line<'_>> = Vec::new();
-
-                        for _ in 0..2 {
-                            let mut line = String::from("");
-                            for _ in 0..bands.len() {
-                                line.push(EQ_NEG_CHAR);
-                            }
-                            let name_span = Line::from(vec![Span::raw(format!("{}", line)).blue()]);
-                            eq_chars.push(name_span);
-                        }
-
-                        for i in 0..height.round() as isize - 3 {
-                            let mut line = String::from("");
-                            for j in 0..bands.len() {
-                                let element = *bands.get(j).unwrap();
-                                if element > 1f32 - (i + 1) as f32 * 1f32 / height {
-                                    line.push(EQ_POS_CHAR);
-                                } else {
-                                    line.push(EQ_NEG_CHAR);
-                                }
-                            }
-                            let name_span = Line::from(vec![Span::raw(format!("{}", line)).blue()]);
-                            eq_chars.push(name_span);
-                        }
-
-                        let mut line = String::from("");
-                        for _ in 0..bands.len() {
-                            line.push(EQ_POS_CHAR);
-                        }
-                        let name_span = Line::from(vec![Span::raw(format!("{}", line)).blue()]);
-                        eq_chars.push(name_span);
-
-                        Paragraph::new(eq_chars)
-                            .centered()
-                            .block(track_eq_block)
-                            .render(current_layout_info[1], buf);
+                let visualizer_area = current_layout_info[1];
+                match self.visualizer_mode {
+                    VisualizerMode::Bars => {
+                        self.render_bars(visualizer_area, visualizer_block, buf)
                     }
-                    None => {
-                        Paragraph::new(Line::from(vec![Span::raw("No freq. info.")]))
-                            .centered()
-                            .block(track_eq_block)
-                            .render(current_layout_info[1], buf);
+                    VisualizerMode::Oscilloscope => {
+                        self.render_oscilloscope(visualizer_area, visualizer_block, buf)
+                    }
+                    VisualizerMode::Spectrogram => {
+                        self.render_spectrogram(visualizer_area, visualizer_block, buf)
+                    }
+                    VisualizerMode::Meters => {
+                        self.render_meters(visualizer_area, visualizer_block, buf)
                     }
                 }
             }
@@ -487,7 +1436,7 @@ impl<'a> Widget for &mut App {
 
                 for n in 0..file_entries.len() {
                     let path = file_entries.get(n).unwrap();
-                    let name = path.as_path().to_str().unwrap();
+                    let name = path.to_string_lossy();
                     let mut span = Span::raw(format!("  {}", name));
 
                     if path.is_dir() {
@@ -520,6 +1469,149 @@ impl<'a> Widget for &mut App {
                     .block(fs_block)
                     .render(layout[1], buf);
             }
+            DisplayMode::Library => {
+                let opened = self.library_browser.opened();
+                let location = match (opened.as_slice(), self.library_browser.grouping()) {
+                    ([], Grouping::Artist) => "Artists".to_string(),
+                    ([], Grouping::Genre) => "Genres".to_string(),
+                    (opened, _) => opened.join(" / "),
+                };
+                let lib_title = Line::from(format!(" Library: {} ", location)).bold();
+
+                let lib_instructions = Line::from(vec![
+                    " Back ".into(),
+                    "<a>".blue().bold(),
+                    " Down ".into(),
+                    "<s>".blue().bold(),
+                    " Up ".into(),
+                    "<d>".blue().bold(),
+                    " Into ".into(),
+                    "<f>".blue().bold(),
+                    " Load ".into(),
+                    "<Enter>".blue().bold(),
+                    " Artists / Genres ".into(),
+                    "<t>".blue().bold(),
+                    " Rescan ".into(),
+                    "<u>".blue().bold(),
+                    " Rebuild ".into(),
+                    "<U> ".blue().bold(),
+                ]);
+
+                let mut lib_block = Block::bordered()
+                    .title(lib_title.left_aligned())
+                    .title_bottom(lib_instructions.centered())
+                    .border_set(border::THICK);
+
+                let scan_progress = match self.scan_progress {
+                    Some(ScanProgress::Looking(found)) => {
+                        Some(format!(" Looking for changes: {} files ", found))
+                    }
+                    Some(ScanProgress::Reading(read, total)) => {
+                        Some(format!(" Reading tags: {} / {} files ", read, total))
+                    }
+                    None if self.library_scan.is_some() => Some(" Loading... ".to_string()),
+                    None => None,
+                };
+                if let Some(scan_progress) = scan_progress {
+                    lib_block = lib_block.title(Line::from(scan_progress).right_aligned());
+                }
+
+                let selected_entry = self.library_browser.selected_entry();
+                let mut lib_lines: Vec<Line<'_>> = self
+                    .library_browser
+                    .entries()
+                    .iter()
+                    .enumerate()
+                    .map(|(n, entry)| {
+                        let span = Span::raw(format!("  {}", entry.name));
+                        match n == selected_entry {
+                            true => return Line::from(span.blue()),
+                            false => return Line::from(span),
+                        }
+                    })
+                    .collect();
+
+                if lib_lines.is_empty() && self.library_scan.is_none() {
+                    let roots: Vec<String> = self
+                        .library_browser
+                        .library()
+                        .roots
+                        .iter()
+                        .map(|root| root.display().to_string())
+                        .collect();
+                    lib_lines.push(Line::from(format!(
+                        "  No music found in {}. Set GIGR_LIBRARY to the directories to scan",
+                        roots.join(", ")
+                    )));
+                }
+
+                let scroll = selected_entry.saturating_sub(1) as u16;
+
+                Paragraph::new(lib_lines)
+                    .left_aligned()
+                    .scroll((scroll, 0))
+                    .block(lib_block)
+                    .render(layout[1], buf);
+            }
+            DisplayMode::SmartPlaylists => {
+                let sp_title = Line::from(" Smart playlists ").bold();
+
+                let sp_instructions = Line::from(vec![
+                    " Down ".into(),
+                    "<s>".blue().bold(),
+                    " Up ".into(),
+                    "<d>".blue().bold(),
+                    " Load ".into(),
+                    "<Enter>".blue().bold(),
+                    " Reload ".into(),
+                    "<u> ".blue().bold(),
+                ]);
+
+                let sp_block = Block::bordered()
+                    .title(sp_title.left_aligned())
+                    .title_bottom(sp_instructions.centered())
+                    .border_set(border::THICK);
+
+                let mut sp_lines: Vec<Line<'_>> = self
+                    .smart_playlists
+                    .iter()
+                    .enumerate()
+                    .map(|(n, smart_playlist)| {
+                        let name = Span::raw(format!("  {}", smart_playlist.name));
+                        let name = match n == self.smart_playlist_cursor {
+                            true => name.blue(),
+                            false => name,
+                        };
+
+                        let detail = match &smart_playlist.query {
+                            Ok(_) => Span::raw(format!("  {}", smart_playlist.definition)).dim(),
+                            Err(error) => Span::raw(format!("  {}", error)).red(),
+                        };
+
+                        return Line::from(vec![name, detail]);
+                    })
+                    .collect();
+
+                if sp_lines.is_empty() {
+                    sp_lines.push(Line::from(format!(
+                        "  No smart playlists yet. Add lines like `Late jazz = genre:jazz year:1955..1965` to {}",
+                        SmartPlaylist::file_path().display()
+                    )));
+                }
+
+                let scroll = self.smart_playlist_cursor.saturating_sub(1) as u16;
+
+                Paragraph::new(sp_lines)
+                    .left_aligned()
+                    .scroll((scroll, 0))
+                    .block(sp_block)
+                    .render(layout[1], buf);
+            }
+        }
+
+        // The search prompt is drawn over whichever mode is open
+        if self.search.is_some() {
+            self.render_search(layout[1], buf);
         }
 
         // CONTROLS ELEMENT
@@ -535,28 +1627,60 @@ impl<'a> Widget for &mut App {
             " Next ".into(),
             "<l>".blue().bold(),
             " Play / Pause ".into(),
-            "<Space> ".blue().bold(),
+            "<Space>".blue().bold(),
+            " Repeat ".into(),
+            "<r>".blue().bold(),
+            " Shuffle ".into(),
+            "<z>".blue().bold(),
+            " Stop after current ".into(),
+            "<x>".blue().bold(),
+            " Crossfade ".into(),
+            "<c>".blue().bold(),
+            " ReplayGain ".into(),
+            "<g> ".blue().bold(),
         ]);
 
-        let ctrl_block = Block::bordered()
+        let mut ctrl_block = Block::bordered()
             .title(ctrl_title.left_aligned())
             .title_bottom(instructions.centered())
             .border_set(border::THICK);
 
+        if let Some(status) = self.status.message() {
+            ctrl_block =
+                ctrl_block.title(Line::from(format!(" {} ", status).yellow()).right_aligned());
+        }
+
         let ctrl_counter_text = Text::from(
             vec![Line::from(vec![
                 //Spans::from(Span::raw(format!("  Volume: {}%", volume))),
                 Span::raw(format!(
-                    "  {} - Volume: {}%",
-                    match playing {
-                        true => {
+                    "  {} - Volume: {}% - Repeat: {:?}{} - Shuffle: {} - Crossfade: {} - ReplayGain: {:?}",
+                    match (stopped, playing) {
+                        (true, _) => {
+                            "Stopped".to_string()
+                        }
+                        (false, true) => {
                             "Playing".to_string()
                         }
-                        false => {
+                        (false, false) => {
                             "Paused ".to_string()
                         }
                     },
-                    volume
+                    volume,
+                    repeat_mode,
+                    match stop_after_current {
+                        true => " (stopping after this track)",
+                        false => "",
+                    },
+                    match shuffled {
+                        true => "On",
+                        false => "Off",
+                    },
+                    match crossfade {
+                        Some(duration) => format!("{}s", duration.as_secs()),
+                        None => "Off".to_string(),
+                    },
+                    replay_gain_mode
                 )),
             ])], //self.counter.to_string().yellow(),
         );
@@ -567,3 +1691,11 @@ impl<'a> Widget for &mut App {
             .render(layout[2], buf);
     }
 }
+
+// Silence has no level in dB, which reads as -inf
+fn format_db(db: f32) -> String {
+    match db.is_finite() {
+        true => return format!("{:.1} dB", db),
+        false => return "-inf dB".to_string(),
+    }
+}
@@ -0,0 +1,453 @@
+use std::collections::HashMap;
+
+use crate::library::{Library, LibraryTrack};
+use crate::playlist::Playlist;
+
+// What the top level of the library browser lists
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Grouping {
+    Artist,
+    Genre,
+}
+
+// One line of the browser, an artist, genre, album or track, with the tracks it stands for
+// as indices into the library, in the order they get queued
+pub struct BrowserEntry {
+    pub name: String,
+    pub tracks: Vec<usize>,
+}
+
+// Walks the library from artists (or genres) to their albums to the tracks on those
+pub struct LibraryBrowser {
+    library: Library,
+    grouping: Grouping,
+
+    // The artist or genre and the album that were moved into, outermost first
+    opened: Vec<BrowserEntry>,
+    // Where the selection was on the levels above, so moving back returns there
+    parent_selections: Vec<usize>,
+
+    entries: Vec<BrowserEntry>,
+    selected_entry: usize,
+}
+
+impl LibraryBrowser {
+    pub fn new(library: Library) -> Self {
+        let mut browser = Self {
+            library,
+            grouping: Grouping::Artist,
+
+            opened: Vec::new(),
+            parent_selections: Vec::new(),
+
+            entries: Vec::new(),
+            selected_entry: 0,
+        };
+        browser.list_entries();
+
+        return browser;
+    }
+
+    pub fn library(&self) -> &Library {
+        return &self.library;
+    }
+
+    // Goes back to the same artist or genre and album where they still exist, so that
+    // a rescan finishing doesn't throw away where the browser was
+    pub fn set_library(&mut self, library: Library) -> () {
+        let opened: Vec<String> = self.opened.iter().map(|entry| entry.name.clone()).collect();
+        let selected_entry = self.selected_entry;
+
+        self.library = library;
+        self.return_to_top();
+
+        for name in &opened {
+            match self.entries.iter().position(|entry| entry.name == *name) {
+                Some(position) => {
+                    self.selected_entry = position;
+                    self.move_forwards();
+                }
+                None => return,
+            }
+        }
+        self.selected_entry = selected_entry.min(self.entries.len().saturating_sub(1));
+    }
+
+    pub fn grouping(&self) -> Grouping {
+        return self.grouping;
+    }
+
+    pub fn toggle_grouping(&mut self) -> () {
+        self.grouping = match self.grouping {
+            Grouping::Artist => Grouping::Genre,
+            Grouping::Genre => Grouping::Artist,
+        };
+        self.return_to_top();
+    }
+
+    pub fn entries(&self) -> &Vec<BrowserEntry> {
+        return &self.entries;
+    }
+
+    pub fn selected_entry(&self) -> usize {
+        return self.selected_entry;
+    }
+
+    // Names of the artist or genre and the album that were moved into
+    pub fn opened(&self) -> Vec<&str> {
+        return self
+            .opened
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect();
+    }
+
+    pub fn move_up(&mut self) -> () {
+        self.selected_entry = self.selected_entry.saturating_sub(1);
+    }
+
+    pub fn move_down(&mut self) -> () {
+        if self.selected_entry + 1 < self.entries.len() {
+            self.selected_entry += 1;
+        }
+    }
+
+    // Tracks are as far down as it goes
+    pub fn move_forwards(&mut self) -> () {
+        if self.opened.len() >= 2 || self.entries.is_empty() {
+            return;
+        }
+
+        let entry = self.entries.swap_remove(self.selected_entry);
+        self.opened.push(entry);
+        self.parent_selections.push(self.selected_entry);
+
+        self.selected_entry = 0;
+        self.list_entries();
+    }
+
+    pub fn move_back(&mut self) -> () {
+        if self.opened.pop().is_none() {
+            return;
+        }
+
+        self.list_entries();
+        self.selected_entry = self.parent_selections.pop().unwrap_or(0);
+    }
+
+    // Every track of the selected artist, genre, album or the selected track
+    pub fn queue_selection(&self) -> Playlist {
+        let mut playlist = Playlist::new();
+
+        if let Some(entry) = self.entries.get(self.selected_entry) {
+            playlist.entries = entry
+                .tracks
+                .iter()
+                .map(|index| self.library.tracks[*index].playlist_entry())
+                .collect();
+        }
+
+        return playlist;
+    }
+
+    fn return_to_top(&mut self) -> () {
+        self.opened.clear();
+        self.parent_selections.clear();
+        self.selected_entry = 0;
+        self.list_entries();
+    }
+
+    fn list_entries(&mut self) -> () {
+        let tracks = &self.library.tracks;
+        let indices = match self.opened.last() {
+            Some(entry) => entry.tracks.clone(),
+            None => sorted_tracks(tracks, self.grouping),
+        };
+
+        self.entries = match self.opened.len() {
+            0 => group_runs(&indices, |index| {
+                group_name(&tracks[index], self.grouping).to_string()
+            }),
+            1 => group_runs(&indices, |index| album_name(&tracks[index], self.grouping))
+                .into_iter()
+                .map(|entry| with_album_year(entry, tracks))
+                .collect(),
+            _ => indices
+                .iter()
+                .map(|index| BrowserEntry {
+                    name: track_label(&tracks[*index]),
+                    tracks: vec![*index],
+                })
+                .collect(),
+        };
+    }
+}
+
+fn group_name(track: &LibraryTrack, grouping: Grouping) -> &str {
+    match grouping {
+        Grouping::Artist => return track.artist_name(),
+        Grouping::Genre => return track.genre_name(),
+    }
+}
+
+// All tracks, ordered by artist or genre, then their albums by year and name,
+// then by disc and track number. The tracks of an album are always next to each other
+pub fn sorted_tracks(tracks: &[LibraryTrack], grouping: Grouping) -> Vec<usize> {
+    // An album is as old as its oldest track, so that a single remastered track
+    // doesn't tear it apart
+    let mut album_years: HashMap<(&str, &str), u32> = HashMap::new();
+    for track in tracks {
+        if let Some(year) = track.year() {
+            album_years
+                .entry((track.artist_name(), track.album_name()))
+                .and_modify(|album_year| *album_year = (*album_year).min(year))
+                .or_insert(year);
+        }
+    }
+
+    let mut indices: Vec<usize> = (0..tracks.len()).collect();
+    indices.sort_by_cached_key(|index| {
+        let track = &tracks[*index];
+        let group = group_name(track, grouping);
+
+        return (
+            group.to_lowercase(),
+            group.to_string(),
+            album_years.get(&(track.artist_name(), track.album_name())),
+            track.album_name().to_lowercase(),
+            track.album_name().to_string(),
+            track.artist_name().to_lowercase(),
+            track.artist_name().to_string(),
+            track.disc_number,
+            track.track_number,
+            track.title.to_lowercase(),
+        );
+    });
+
+    return indices;
+}
+
+// Neighbouring tracks with the same name end up in one entry
+fn group_runs(indices: &[usize], name: impl Fn(usize) -> String) -> Vec<BrowserEntry> {
+    let mut entries: Vec<BrowserEntry> = Vec::new();
+
+    for index in indices {
+        let name = name(*index);
+        match entries.last_mut() {
+            Some(entry) if entry.name == name => entry.tracks.push(*index),
+            _ => entries.push(BrowserEntry {
+                name,
+                tracks: vec![*index],
+            }),
+        }
+    }
+
+    return entries;
+}
+
+// Albums listed under a genre come from all kinds of artists, so they say whose they are
+fn album_name(track: &LibraryTrack, grouping: Grouping) -> String {
+    match grouping {
+        Grouping::Artist => return track.album_name().to_string(),
+        Grouping::Genre => return format!("{} - {}", track.album_name(), track.artist_name()),
+    }
+}
+
+// Puts the year of the album's oldest track in front of its name
+fn with_album_year(entry: BrowserEntry, tracks: &[LibraryTrack]) -> BrowserEntry {
+    let year = entry
+        .tracks
+        .iter()
+        .filter_map(|index| tracks[*index].year())
+        .min()
+        .map(|year| year.to_string())
+        .unwrap_or_default();
+
+    BrowserEntry {
+        name: format!("{:>4}  {}", year, entry.name),
+        tracks: entry.tracks,
+    }
+}
+
+// Tracks by someone other than the album artist, as on compilations, name who it's by
+fn track_label(track: &LibraryTrack) -> String {
+    let number = track
+        .track_number
+        .map(|number| format!("{:>2}.", number))
+        .unwrap_or_default();
+
+    match &track.artist {
+        Some(artist) if artist != track.artist_name() => {
+            return format!("{:>3} {} - {}", number, track.title, artist);
+        }
+        _ => return format!("{:>3} {}", number, track.title),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn track(
+        artist: &str,
+        album: &str,
+        date: &str,
+        disc: u32,
+        number: u32,
+        genre: &str,
+    ) -> LibraryTrack {
+        LibraryTrack {
+            path: format!("/music/{}/{}/{}-{}.flac", artist, album, disc, number),
+            cue_track: None,
+
+            title: format!("{} {}-{}", album, disc, number),
+            artist: Some(artist.to_string()),
+            album: Some(album.to_string()),
+            album_artist: None,
+            genre: Some(genre.to_string()),
+            date: Some(date.to_string()),
+            track_number: Some(number),
+            disc_number: Some(disc),
+            rating: None,
+
+            duration_secs: 60.0,
+        }
+    }
+
+    fn library() -> Library {
+        let mut library = Library::new(vec![PathBuf::from("/music")]);
+        library.tracks = vec![
+            track("Miles Davis", "Bitches Brew", "1970", 2, 1, "Jazz"),
+            track("miles davis", "Kind of Blue", "1959-08-17", 1, 2, "Jazz"),
+            track("Miles Davis", "Bitches Brew", "1970", 1, 2, "Fusion"),
+            track("Aphex Twin", "Drukqs", "2001", 1, 1, "Electronic"),
+            track("Miles Davis", "Bitches Brew", "1970", 1, 1, "Jazz"),
+            track("miles davis", "Kind of Blue", "1959", 1, 1, "Jazz"),
+        ];
+        return library;
+    }
+
+    fn names(browser: &LibraryBrowser) -> Vec<&str> {
+        return browser
+            .entries()
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect();
+    }
+
+    #[test]
+    fn artists_albums_and_tracks_are_sorted() {
+        let mut browser = LibraryBrowser::new(library());
+        assert_eq!(
+            names(&browser),
+            vec!["Aphex Twin", "Miles Davis", "miles davis"]
+        );
+
+        browser.move_down();
+        browser.move_forwards();
+        assert_eq!(browser.opened(), vec!["Miles Davis"]);
+        assert_eq!(names(&browser), vec!["1970  Bitches Brew"]);
+
+        browser.move_forwards();
+        let titles: Vec<&str> = browser
+            .entries()
+            .iter()
+            .map(|entry| entry.name.trim_start())
+            .collect();
+        assert_eq!(
+            titles,
+            vec![
+                "1. Bitches Brew 1-1",
+                "2. Bitches Brew 1-2",
+                "1. Bitches Brew 2-1"
+            ]
+        );
+
+        // There's nothing below tracks
+        browser.move_forwards();
+        assert_eq!(browser.opened().len(), 2);
+    }
+
+    #[test]
+    fn moving_back_returns_to_the_selection() {
+        let mut browser = LibraryBrowser::new(library());
+        browser.move_down();
+        browser.move_down();
+        browser.move_forwards();
+        browser.move_back();
+
+        assert_eq!(browser.selected_entry(), 2);
+        assert!(browser.opened().is_empty());
+        assert_eq!(names(&browser).len(), 3);
+    }
+
+    #[test]
+    fn any_level_can_be_queued() {
+        let mut browser = LibraryBrowser::new(library());
+        browser.move_down();
+
+        let paths: Vec<String> = browser
+            .queue_selection()
+            .entries
+            .iter()
+            .map(|entry| entry.path.to_string_lossy().to_string())
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                "/music/Miles Davis/Bitches Brew/1-1.flac",
+                "/music/Miles Davis/Bitches Brew/1-2.flac",
+                "/music/Miles Davis/Bitches Brew/2-1.flac",
+            ]
+        );
+
+        browser.move_forwards();
+        browser.move_forwards();
+        browser.move_down();
+        assert_eq!(browser.queue_selection().entries.len(), 1);
+    }
+
+    #[test]
+    fn a_new_library_keeps_the_position() {
+        let mut browser = LibraryBrowser::new(library());
+        browser.move_down();
+        browser.move_forwards();
+        browser.move_forwards();
+        browser.move_down();
+
+        let mut rescanned = library();
+        rescanned.tracks.remove(3);
+        browser.set_library(rescanned);
+        assert_eq!(browser.opened(), vec!["Miles Davis", "1970  Bitches Brew"]);
+        assert_eq!(browser.selected_entry(), 1);
+
+        // The artist is gone, so there's nothing to return to
+        let mut rescanned = library();
+        rescanned
+            .tracks
+            .retain(|track| track.artist_name() != "Miles Davis");
+        browser.set_library(rescanned);
+        assert!(browser.opened().is_empty());
+        assert_eq!(browser.selected_entry(), 0);
+    }
+
+    #[test]
+    fn genres_list_albums_by_year_with_their_artist() {
+        let mut browser = LibraryBrowser::new(library());
+        browser.toggle_grouping();
+        assert_eq!(names(&browser), vec!["Electronic", "Fusion", "Jazz"]);
+
+        browser.move_down();
+        browser.move_down();
+        browser.move_forwards();
+        assert_eq!(
+            names(&browser),
+            vec![
+                "1959  Kind of Blue - miles davis",
+                "1970  Bitches Brew - Miles Davis"
+            ]
+        );
+    }
+}
@@ -0,0 +1,141 @@
+use std::cmp::Reverse;
+use std::fs::read_dir;
+use std::path::{Path, PathBuf};
+
+use color_eyre::Result;
+
+use image::{DynamicImage, ImageReader};
+
+use lofty::file::TaggedFileExt;
+use lofty::picture::{Picture, PictureType};
+use lofty::probe::Probe;
+
+const IMAGE_EXTENSIONS: [&str; 5] = ["jpg", "jpeg", "png", "webp", "gif"];
+
+// Images next to the music, best first. Anything else only gets used when none of these exist
+const COVER_NAMES: [&str; 4] = ["cover", "folder", "front", "album"];
+
+// The cover art for a song: the front cover in its tags, or else an image in its directory.
+// No cover art is not an error, an unreadable directory or image is
+pub fn load_cover(song_path: &Path) -> Result<Option<DynamicImage>> {
+    if let Some(picture) = embedded_cover(song_path)
+        && let Ok(image) = image::load_from_memory(picture.data())
+    {
+        return Ok(Some(image));
+    }
+
+    let Some(dir_path) = song_path.parent() else {
+        return Ok(None);
+    };
+
+    match folder_cover(dir_path)? {
+        Some(path) => {
+            return Ok(Some(
+                ImageReader::open(path)?.with_guessed_format()?.decode()?,
+            ));
+        }
+        None => return Ok(None),
+    }
+}
+
+// The front cover if the tags say which picture that is, the first picture otherwise
+fn embedded_cover(song_path: &Path) -> Option<Picture> {
+    let tagged_file = Probe::open(song_path)
+        .ok()?
+        .guess_file_type()
+        .ok()?
+        .read()
+        .ok()?;
+
+    let pictures: Vec<&Picture> = tagged_file
+        .tags()
+        .iter()
+        .flat_map(|tag| tag.pictures())
+        .collect();
+
+    return pictures
+        .iter()
+        .find(|picture| picture.pic_type() == PictureType::CoverFront)
+        .or(pictures.first())
+        .map(|picture| (*picture).clone());
+}
+
+// Picks the image with the best name out of the directory, going alphabetically among equals
+fn folder_cover(dir_path: &Path) -> Result<Option<PathBuf>> {
+    let mut images: Vec<PathBuf> = read_dir(dir_path)?
+        .filter_map(|res| res.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && is_image(path))
+        .collect();
+
+    images.sort_by_key(|path| (Reverse(name_priority(path)), path.clone()));
+
+    return Ok(images.into_iter().next());
+}
+
+fn is_image(path: &Path) -> bool {
+    return path.extension().is_some_and(|ext| {
+        IMAGE_EXTENSIONS
+            .iter()
+            .any(|image_ext| ext.eq_ignore_ascii_case(image_ext))
+    });
+}
+
+// Higher is better, 0 for names that aren't on the list
+fn name_priority(path: &Path) -> usize {
+    let Some(stem) = path.file_stem() else {
+        return 0;
+    };
+    let stem = stem.to_string_lossy().to_lowercase();
+
+    match COVER_NAMES.iter().position(|name| *name == stem) {
+        Some(index) => return COVER_NAMES.len() - index,
+        None => return 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use crate::testing::test_dir;
+
+    #[test]
+    fn folder_images_are_ranked_by_name() {
+        let dir = test_dir("cover-folder");
+        for name in [
+            "back.jpg",
+            "artwork.png",
+            "album.png",
+            "front.webp",
+            "Folder.JPG",
+            "cover.png",
+            "cover.jpg",
+            "cover.txt",
+        ] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        // Only files count
+        fs::create_dir(dir.join("cover.gif")).unwrap();
+
+        // Best first, ties going alphabetically, which puts .jpg before .png
+        let ranked = [
+            "cover.jpg",
+            "cover.png",
+            "Folder.JPG",
+            "front.webp",
+            "album.png",
+            "artwork.png",
+            "back.jpg",
+        ];
+        for name in ranked {
+            assert_eq!(folder_cover(&dir).unwrap(), Some(dir.join(name)));
+            fs::remove_file(dir.join(name)).unwrap();
+        }
+        assert_eq!(folder_cover(&dir).unwrap(), None);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,316 @@
+use std::path::Path;
+use std::time::Duration;
+
+use lofty::file::TaggedFileExt;
+use lofty::prelude::ItemKey;
+use lofty::probe::Probe;
+
+use serde::{Deserialize, Serialize};
+
+// CUE sheet timestamps count frames, of which there are 75 in a second
+const FRAMES_PER_SECOND: u32 = 75;
+
+// Vorbis comment that FLAC rips carry their CUE sheet in
+const EMBEDDED_CUE_SHEET_KEY: &str = "CUESHEET";
+
+// One track of a CUE sheet, playing the part of a file between `start` and `end`
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CueTrack {
+    pub number: u32,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub album: Option<String>,
+
+    pub start: Duration,
+    // The last track of a file plays until the file ends
+    pub end: Option<Duration>,
+}
+
+// A track along with the file it's in, as the sheet names it
+pub struct CueEntry {
+    pub file: String,
+    pub track: CueTrack,
+}
+
+pub fn is_cue_sheet(path: &Path) -> bool {
+    return path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("cue"));
+}
+
+// The CUE sheet in a file's tags, whose FILE lines all mean that file itself
+pub fn embedded_cue_sheet(path: &Path) -> Option<String> {
+    let tagged_file = Probe::open(path).ok()?.read().ok()?;
+
+    return tagged_file
+        .primary_tag()?
+        .get_string(&ItemKey::Unknown(EMBEDDED_CUE_SHEET_KEY.to_string()))
+        .map(|sheet| sheet.to_string());
+}
+
+// Reads the audio tracks of a CUE sheet. Tracks start at their INDEX 01,
+// a pregap (INDEX 00) is left to the end of the track before
+pub fn parse_cue_sheet(contents: &str) -> Vec<CueEntry> {
+    let mut entries: Vec<CueEntry> = Vec::new();
+
+    let mut album: Option<String> = None;
+    let mut album_performer: Option<String> = None;
+
+    let mut file: Option<String> = None;
+    let mut track: Option<CueTrack> = None;
+    // Tracks without an INDEX 01 have nowhere to start from and get dropped
+    let mut has_start = false;
+
+    // A track is done once the next one or the next file starts
+    let finish_track =
+        |entries: &mut Vec<CueEntry>, file: &Option<String>, track: Option<CueTrack>, has_start| {
+            if let (Some(file), Some(track), true) = (file, track, has_start) {
+                entries.push(CueEntry {
+                    file: file.clone(),
+                    track,
+                });
+            }
+        };
+
+    for line in contents.trim_start_matches('\u{feff}').lines() {
+        let line = line.trim();
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+
+        match command.to_uppercase().as_str() {
+            "FILE" => {
+                // A track whose pregap is at the end of the previous file only starts in this
+                // one, so it is kept going
+                if has_start {
+                    finish_track(&mut entries, &file, track.take(), has_start);
+                    has_start = false;
+                }
+                file = Some(file_name(rest));
+            }
+            "TRACK" => {
+                finish_track(&mut entries, &file, track.take(), has_start);
+                has_start = false;
+
+                let mut words = rest.split_whitespace();
+                let number = words.next().and_then(|n| n.parse().ok()).unwrap_or(0);
+
+                // Data tracks of mixed mode CDs can't be played
+                if words
+                    .next()
+                    .is_some_and(|mode| mode.eq_ignore_ascii_case("AUDIO"))
+                {
+                    track = Some(CueTrack {
+                        number,
+                        title: None,
+                        performer: album_performer.clone(),
+                        album: album.clone(),
+                        start: Duration::ZERO,
+                        end: None,
+                    });
+                }
+            }
+            "TITLE" => match &mut track {
+                Some(track) => track.title = Some(unquote(rest)),
+                None => album = Some(unquote(rest)),
+            },
+            "PERFORMER" => match &mut track {
+                Some(track) => track.performer = Some(unquote(rest)),
+                None => album_performer = Some(unquote(rest)),
+            },
+            "INDEX" => {
+                let mut words = rest.split_whitespace();
+                if words.next().and_then(|n| n.parse::<u32>().ok()) == Some(1)
+                    && let Some(track) = &mut track
+                    && let Some(start) = words.next().and_then(parse_timestamp)
+                {
+                    track.start = start;
+                    has_start = true;
+                }
+            }
+            _ => {}
+        }
+    }
+    finish_track(&mut entries, &file, track.take(), has_start);
+
+    // Within a file, each track ends where the one after it starts
+    for i in 1..entries.len() {
+        if entries[i].file == entries[i - 1].file {
+            entries[i - 1].track.end = Some(entries[i].track.start);
+        }
+    }
+
+    return entries;
+}
+
+// FILE "name" TYPE, the name may be unquoted if it has no spaces
+fn file_name(rest: &str) -> String {
+    if rest.starts_with('"') {
+        return unquote(rest);
+    }
+
+    match rest.rsplit_once(char::is_whitespace) {
+        Some((name, _file_type)) => return name.trim().to_string(),
+        None => return rest.to_string(),
+    }
+}
+
+fn unquote(text: &str) -> String {
+    match text.strip_prefix('"') {
+        Some(quoted) => return quoted.split('"').next().unwrap_or("").to_string(),
+        None => return text.to_string(),
+    }
+}
+
+// mm:ss:ff, where minutes can go past 59
+fn parse_timestamp(timestamp: &str) -> Option<Duration> {
+    let mut parts = timestamp.split(':').map(|part| part.parse::<u32>().ok());
+
+    let (Some(Some(minutes)), Some(Some(seconds)), Some(Some(frames)), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+
+    let frames = ((minutes * 60 + seconds) * FRAMES_PER_SECOND + frames) as u64;
+
+    return Some(Duration::from_nanos(
+        frames * 1_000_000_000 / FRAMES_PER_SECOND as u64,
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seconds(seconds: u64, frames: u64) -> Duration {
+        return Duration::from_nanos((seconds * 75 + frames) * 1_000_000_000 / 75);
+    }
+
+    // File, number, start and end of every entry
+    fn spans(entries: &[CueEntry]) -> Vec<(&str, u32, Duration, Option<Duration>)> {
+        return entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry.file.as_str(),
+                    entry.track.number,
+                    entry.track.start,
+                    entry.track.end,
+                )
+            })
+            .collect();
+    }
+
+    #[test]
+    fn timestamps_count_75_frames_a_second() {
+        assert_eq!(parse_timestamp("00:00:00"), Some(Duration::ZERO));
+        assert_eq!(parse_timestamp("01:02:37"), Some(seconds(62, 37)));
+        assert_eq!(parse_timestamp("00:01:74"), Some(seconds(1, 74)));
+        // Minutes go past an hour on long rips
+        assert_eq!(parse_timestamp("75:00:00"), Some(seconds(4500, 0)));
+
+        assert_eq!(parse_timestamp("01:02"), None);
+        assert_eq!(parse_timestamp("01:02:03:04"), None);
+        assert_eq!(parse_timestamp("01:xx:03"), None);
+    }
+
+    #[test]
+    fn pregaps_stay_with_the_track_before() {
+        let sheet = "FILE \"album.flac\" WAVE
+  TRACK 01 AUDIO
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    INDEX 00 03:10:00
+    INDEX 01 03:12:30
+  TRACK 03 AUDIO
+    INDEX 01 07:00:00
+";
+
+        assert_eq!(
+            spans(&parse_cue_sheet(sheet)),
+            vec![
+                ("album.flac", 1, Duration::ZERO, Some(seconds(192, 30))),
+                ("album.flac", 2, seconds(192, 30), Some(seconds(420, 0))),
+                // The last track plays until the file ends
+                ("album.flac", 3, seconds(420, 0), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn tracks_end_within_their_file() {
+        let sheet = "FILE \"one.wav\" WAVE
+  TRACK 01 AUDIO
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    INDEX 01 02:00:00
+  TRACK 03 AUDIO
+    INDEX 00 04:58:00
+FILE two.wav WAVE
+    INDEX 01 00:00:00
+  TRACK 04 AUDIO
+    INDEX 01 03:00:00
+";
+
+        assert_eq!(
+            spans(&parse_cue_sheet(sheet)),
+            vec![
+                ("one.wav", 1, Duration::ZERO, Some(seconds(120, 0))),
+                ("one.wav", 2, seconds(120, 0), None),
+                // Its pregap is at the end of the first file, but it starts in the second
+                ("two.wav", 3, Duration::ZERO, Some(seconds(180, 0))),
+                ("two.wav", 4, seconds(180, 0), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn tracks_inherit_the_album_performer() {
+        let sheet = "\u{feff}PERFORMER \"Miles Davis\"
+TITLE \"Kind of Blue\"
+FILE \"album.flac\" WAVE
+  TRACK 01 AUDIO
+    TITLE \"So What\"
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    TITLE \"Freddie Freeloader\"
+    PERFORMER \"Miles Davis Sextet\"
+    INDEX 01 09:22:00
+";
+
+        let tracks: Vec<(Option<String>, Option<String>, Option<String>)> = parse_cue_sheet(sheet)
+            .into_iter()
+            .map(|entry| (entry.track.title, entry.track.performer, entry.track.album))
+            .collect();
+
+        let some = |text: &str| Some(text.to_string());
+        assert_eq!(
+            tracks,
+            vec![
+                (some("So What"), some("Miles Davis"), some("Kind of Blue")),
+                (
+                    some("Freddie Freeloader"),
+                    some("Miles Davis Sextet"),
+                    some("Kind of Blue")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn unplayable_tracks_are_dropped() {
+        let sheet = "FILE \"disc.bin\" BINARY
+  TRACK 01 MODE1/2352
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    INDEX 00 10:00:00
+  TRACK 03 AUDIO
+    INDEX 01 12:00:00
+";
+
+        assert_eq!(
+            spans(&parse_cue_sheet(sheet)),
+            vec![("disc.bin", 3, seconds(720, 0), None)]
+        );
+    }
+}
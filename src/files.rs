@@ -2,9 +2,10 @@ use std::path::PathBuf;
 
 use std::fs::read_dir;
 
-use color_eyre::eyre::Error;
+use color_eyre::Result;
 
 use crate::expand_tilde;
+use crate::playlist::{Playlist, is_playlist, read_playlist};
 
 pub struct FileSelector {
     running_path: PathBuf,
@@ -15,26 +16,31 @@ pub struct FileSelector {
 }
 
 impl FileSelector {
-    pub fn new(start_path: PathBuf) -> Self {
-        let contents = match Self::read_contents(start_path.clone()) {
-            Ok(res) => res,
+    // Starts in the home directory if `start_path` can't be read
+    pub fn new(start_path: PathBuf) -> Result<Self> {
+        let (running_path, contents) = match Self::read_contents(start_path.clone()) {
+            Ok(res) => (start_path, res),
             _ => {
                 let path = expand_tilde("~/");
-                Self::read_contents(path.clone()).unwrap()
+                let contents = Self::read_contents(path.clone())?;
+                (path, contents)
             }
         };
 
-        Self {
-            running_path: start_path.clone(),
-            contents: contents,
+        let mut file_selector = Self {
+            running_path,
+            contents,
 
             selected_entry: 0,
             is_file: true,
-        }
+        };
+        file_selector.eval_selection();
+
+        Ok(file_selector)
     }
 
-    fn read_contents(path: PathBuf) -> Result<Vec<PathBuf>, Error> {
-        let mut entries: Vec<_> = read_dir(path)?.map(|res| res.unwrap()).collect();
+    fn read_contents(path: PathBuf) -> Result<Vec<PathBuf>> {
+        let mut entries = read_dir(path)?.collect::<Result<Vec<_>, _>>()?;
 
         entries.sort_by_key(|dir| dir.path());
 
@@ -49,8 +55,11 @@ impl FileSelector {
     }
 
     fn eval_selection(&mut self) {
-        let entry = self.contents.get(self.selected_entry).unwrap();
-        self.is_file = entry.is_file();
+        // An empty directory has nothing selected, which is treated like a file
+        self.is_file = self
+            .contents
+            .get(self.selected_entry)
+            .is_none_or(|entry| entry.is_file());
     }
 
     pub fn contents(&self) -> &Vec<PathBuf> {
@@ -61,6 +70,25 @@ impl FileSelector {
         return self.selected_entry;
     }
 
+    pub fn running_path(&self) -> &PathBuf {
+        return &self.running_path;
+    }
+
+    // Re-reads the current directory, e.g. after a file was written into it.
+    // The selected entry stays selected, or its position does if it's gone
+    pub fn refresh(&mut self) {
+        if let Ok(contents) = FileSelector::read_contents(self.running_path.clone()) {
+            let selected_path = self.contents.get(self.selected_entry);
+            self.selected_entry = selected_path
+                .and_then(|selected_path| contents.iter().position(|path| path == selected_path))
+                .unwrap_or(self.selected_entry)
+                .min(contents.len().saturating_sub(1));
+
+            self.contents = contents;
+            self.eval_selection();
+        }
+    }
+
     pub fn move_up(&mut self) {
         if self.selected_entry == 0 {
             return;
@@ -70,36 +98,54 @@ impl FileSelector {
         self.eval_selection();
     }
     pub fn move_down(&mut self) {
-        if self.selected_entry >= self.contents().len() - 1 {
+        if self.selected_entry + 1 >= self.contents().len() {
             return;
         }
 
         self.selected_entry += 1;
         self.eval_selection();
     }
-    pub fn move_back(&mut self) {
-        self.running_path.pop();
-        self.selected_entry = 0;
-        self.contents = FileSelector::read_contents(self.running_path.clone()).unwrap();
-        self.eval_selection();
+    // Moving into a directory that can't be read fails and stays where it is
+    pub fn move_back(&mut self) -> Result<()> {
+        let mut path = self.running_path.clone();
+        path.pop();
+        self.move_to(path)
     }
-    pub fn move_forwards(&mut self) {
-        if !self.is_file {
-            self.running_path = self.contents().get(self.selected_entry).unwrap().clone();
-            self.selected_entry = 0;
-            self.contents = FileSelector::read_contents(self.running_path.clone()).unwrap();
-            self.eval_selection();
+    pub fn move_forwards(&mut self) -> Result<()> {
+        if self.is_file {
+            return Ok(());
+        }
+
+        match self.contents().get(self.selected_entry) {
+            Some(path) => self.move_to(path.clone()),
+            None => Ok(()),
         }
     }
-    pub fn queue_selection(&self) -> Option<Vec<PathBuf>> {
+    fn move_to(&mut self, path: PathBuf) -> Result<()> {
+        self.contents = FileSelector::read_contents(path.clone())?;
+        self.running_path = path;
+        self.selected_entry = 0;
+        self.eval_selection();
+
+        Ok(())
+    }
+    // Everything that selecting the current entry adds to the queue.
+    // Fails when the selection is a directory or playlist that can't be read
+    pub fn queue_selection(&self) -> Result<Playlist> {
+        let Some(path) = self.contents().get(self.selected_entry).cloned() else {
+            return Ok(Playlist::new());
+        };
+
         if !self.is_file {
-            let path = self.contents().get(self.selected_entry).unwrap().clone();
-            return Some(Self::read_contents(path).unwrap());
+            let mut paths = Self::read_contents(path)?;
+            paths.retain(|path| path.is_file());
+            return Ok(Playlist::from_paths(paths));
         }
 
-        let file_vec: Vec<PathBuf> =
-            vec![self.contents().get(self.selected_entry).unwrap().clone()];
+        if is_playlist(&path) {
+            return Ok(read_playlist(&path)?);
+        }
 
-        return Some(file_vec);
+        return Ok(Playlist::from_file(path));
     }
 }
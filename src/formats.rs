@@ -0,0 +1,261 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::time::Duration;
+
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+
+use lofty::file::FileType;
+use lofty::probe::Probe;
+
+use rodio::source::SeekError;
+use rodio::{Decoder, Source};
+
+#[cfg(feature = "opus")]
+use crate::opus::OpusDecoder;
+use crate::song::Song;
+
+pub type AudioSource = Box<dyn Source + Send>;
+
+// Works out the format from the file's header, so that the extension doesn't matter.
+// Fails for unreadable files and for formats that can't be played, which makes this the
+// check for everything that ends up in the queue, be it from the file selector or a playlist
+pub fn detect_format(path: &Path) -> Result<FileType> {
+    // Without a path, lofty can't fall back to guessing from the extension
+    let probe = Probe::new(BufReader::new(File::open(path)?)).guess_file_type()?;
+
+    match probe.file_type() {
+        Some(file_type) if is_decodable(file_type) => return Ok(file_type),
+        Some(file_type) => return Err(eyre!("{:?} files can't be played", file_type)),
+        None => return Err(eyre!("unrecognised format")),
+    }
+}
+
+// Everything the enabled rodio/symphonia features can decode, ALAC comes in MP4 files next
+// to AAC. Opus gets its own decoder, which is only there with the `opus` feature
+fn is_decodable(file_type: FileType) -> bool {
+    match file_type {
+        FileType::Aac
+        | FileType::Aiff
+        | FileType::Flac
+        | FileType::Mpeg
+        | FileType::Mp4
+        | FileType::Vorbis
+        | FileType::Wav => return true,
+        FileType::Opus => return cfg!(feature = "opus"),
+        _ => return false,
+    }
+}
+
+// Tells symphonia what to expect, in place of the file's extension
+fn format_hint(file_type: FileType) -> &'static str {
+    match file_type {
+        FileType::Aac => return "aac",
+        FileType::Aiff => return "aiff",
+        FileType::Flac => return "flac",
+        FileType::Mp4 => return "m4a",
+        FileType::Vorbis => return "ogg",
+        FileType::Wav => return "wav",
+        _ => return "mp3",
+    }
+}
+
+// Opens a decoder for the file, picking the Opus decoder where rodio can't help
+pub fn open_source(path: &Path) -> Result<AudioSource> {
+    let file_type = detect_format(path)?;
+
+    #[cfg(feature = "opus")]
+    if file_type == FileType::Opus {
+        return Ok(Box::new(OpusDecoder::new(File::open(path)?)?));
+    }
+
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
+
+    let decoder = Decoder::builder()
+        .with_data(BufReader::new(file))
+        .with_byte_len(len)
+        .with_seekable(true)
+        .with_hint(format_hint(file_type))
+        .build()?;
+
+    return Ok(Box::new(decoder));
+}
+
+// Opens the song for playback, only the part of the file its CUE track covers if it has one
+pub fn open_song(song: &Song) -> Result<AudioSource> {
+    let source = open_source(&song.file_path_as_path())?;
+
+    match &song.cue_track {
+        Some(track) => return Ok(Box::new(TrackRange::new(source, track.start, track.end)?)),
+        None => return Ok(source),
+    }
+}
+
+// Plays the part of a source between two offsets.
+// Positions, seeks and the duration are all relative to the start of that part
+struct TrackRange {
+    inner: AudioSource,
+    start: Duration,
+    length: Option<Duration>,
+    // Samples left until the end offset
+    remaining: Option<u64>,
+}
+
+impl TrackRange {
+    fn new(mut inner: AudioSource, start: Duration, end: Option<Duration>) -> Result<Self> {
+        inner
+            .try_seek(start)
+            .map_err(|error| eyre!("couldn't seek to the start of the track: {}", error))?;
+
+        let length = end.map(|end| end.saturating_sub(start));
+
+        let mut range = Self {
+            inner,
+            start,
+            length,
+            remaining: None,
+        };
+        range.remaining = length.map(|length| range.samples_in(length));
+
+        return Ok(range);
+    }
+
+    // Counts whole frames, so that the range never ends in the middle of one
+    fn samples_in(&self, duration: Duration) -> u64 {
+        let frames = (duration.as_secs_f64() * self.inner.sample_rate() as f64) as u64;
+        return frames * self.inner.channels() as u64;
+    }
+}
+
+impl Iterator for TrackRange {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if let Some(remaining) = &mut self.remaining {
+            if *remaining == 0 {
+                return None;
+            }
+            *remaining -= 1;
+        }
+
+        return self.inner.next();
+    }
+}
+
+impl Source for TrackRange {
+    fn current_span_len(&self) -> Option<usize> {
+        let span_len = self.inner.current_span_len();
+
+        match self.remaining {
+            Some(remaining) => {
+                let remaining = remaining as usize;
+                return Some(span_len.map_or(remaining, |len| len.min(remaining)));
+            }
+            None => return span_len,
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        return self.inner.channels();
+    }
+
+    fn sample_rate(&self) -> u32 {
+        return self.inner.sample_rate();
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        return self.length.or_else(|| {
+            self.inner
+                .total_duration()
+                .map(|total| total.saturating_sub(self.start))
+        });
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        let pos = match self.length {
+            Some(length) => pos.min(length),
+            None => pos,
+        };
+
+        self.inner.try_seek(self.start + pos)?;
+        self.remaining = self
+            .length
+            .map(|length| self.samples_in(length.saturating_sub(pos)));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ops::Range;
+
+    use rodio::buffer::SamplesBuffer;
+
+    // Ten seconds at 100 Hz, where every sample is its own index
+    fn numbered(channels: u16) -> AudioSource {
+        let samples: Vec<f32> = (0..1000 * channels).map(f32::from).collect();
+        return Box::new(SamplesBuffer::new(channels, 100, samples));
+    }
+
+    fn range(channels: u16, start: u64, end: Option<u64>) -> TrackRange {
+        return TrackRange::new(
+            numbered(channels),
+            Duration::from_secs(start),
+            end.map(Duration::from_secs),
+        )
+        .unwrap();
+    }
+
+    fn samples(range: TrackRange) -> Vec<f32> {
+        return range.collect();
+    }
+
+    fn numbers(numbers: Range<u16>) -> Vec<f32> {
+        return numbers.map(f32::from).collect();
+    }
+
+    #[test]
+    fn a_track_stops_where_the_next_one_starts() {
+        let track = range(1, 2, Some(5));
+        assert_eq!(track.total_duration(), Some(Duration::from_secs(3)));
+        assert_eq!(samples(track), numbers(200..500));
+
+        // The next track picks up right at the first sample this one left out
+        assert_eq!(samples(range(1, 5, Some(7))), numbers(500..700));
+    }
+
+    #[test]
+    fn the_last_track_plays_until_the_file_ends() {
+        let track = range(1, 8, None);
+        assert_eq!(track.total_duration(), Some(Duration::from_secs(2)));
+        assert_eq!(samples(track), numbers(800..1000));
+    }
+
+    #[test]
+    fn tracks_end_on_whole_frames() {
+        assert_eq!(samples(range(2, 1, Some(2))), numbers(200..400));
+    }
+
+    #[test]
+    fn seeking_is_relative_to_the_track_start() {
+        let mut track = range(1, 2, Some(5));
+        track.try_seek(Duration::from_secs(1)).unwrap();
+        assert_eq!(samples(track), numbers(300..500));
+
+        // Seeking back to the start of the track doesn't reach into the one before
+        let mut track = range(2, 2, Some(5));
+        track.by_ref().take(100).for_each(drop);
+        track.try_seek(Duration::ZERO).unwrap();
+        assert_eq!(samples(track), numbers(400..1000));
+
+        // Past the end there is nothing left to play
+        let mut track = range(1, 2, Some(5));
+        track.try_seek(Duration::from_secs(9)).unwrap();
+        assert_eq!(samples(track), Vec::<f32>::new());
+    }
+}
@@ -0,0 +1,629 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::fs::{self, Metadata, read_dir};
+use std::mem;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use color_eyre::Result;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cue::{CueTrack, is_cue_sheet};
+use crate::loader::SongLoader;
+use crate::playlist::{Playlist, PlaylistEntry};
+use crate::song::Song;
+use crate::{expand_tilde, xdg_dir};
+
+// Bumped whenever the layout of `Library` changes, older indexes are then scanned again
+const LIBRARY_VERSION: u32 = 3;
+
+const LIBRARY_FILE_NAME: &str = "library.json";
+
+// Directories the library is made of, separated by colons
+const LIBRARY_ROOTS_VARIABLE: &str = "GIGR_LIBRARY";
+const DEFAULT_LIBRARY_ROOT: &str = "~/Music";
+
+// How many files are looked at between progress reports while looking for changes
+const PROGRESS_INTERVAL: usize = 100;
+
+// What a file looked like when its tags were read. As long as it still looks the same,
+// its tags are taken from the index instead of being read again
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct FileStamp {
+    pub modified_secs: i64,
+    pub modified_nanos: i64,
+    pub size: u64,
+    pub inode: u64,
+}
+
+impl FileStamp {
+    fn of(metadata: &Metadata) -> Self {
+        Self {
+            modified_secs: metadata.mtime(),
+            modified_nanos: metadata.mtime_nsec(),
+            size: metadata.size(),
+            inode: metadata.ino(),
+        }
+    }
+}
+
+// How far a scan got
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ScanProgress {
+    // Files found so far while walking the roots
+    Looking(usize),
+    // Files whose tags were read, out of those that are new or changed
+    Reading(usize, usize),
+}
+
+// The files of one directory, as they were found by a scan
+struct ScannedDir {
+    path: PathBuf,
+    files: Vec<(PathBuf, FileStamp)>,
+}
+
+// The tags of one track, as far as browsing the library needs them
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LibraryTrack {
+    pub path: String,
+    // Tracks from CUE sheets only play a part of the file
+    pub cue_track: Option<CueTrack>,
+
+    pub title: String,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub genre: Option<String>,
+    pub date: Option<String>,
+    pub track_number: Option<u32>,
+    pub disc_number: Option<u32>,
+    // Stars out of 5
+    pub rating: Option<u8>,
+
+    pub duration_secs: f64,
+}
+
+impl LibraryTrack {
+    fn from_song(song: &Song) -> Self {
+        Self {
+            path: song.file_path_clone(),
+            cue_track: song.cue_track.clone(),
+
+            title: song.title_clone(),
+            artist: song.artist.clone(),
+            album: song.album.clone(),
+            album_artist: song.album_artist.clone(),
+            genre: song.genre.clone(),
+            date: song.date.clone(),
+            track_number: song.track_number,
+            disc_number: song.disc_number,
+            rating: song.rating,
+
+            duration_secs: song.duration.as_secs_f64(),
+        }
+    }
+
+    // Tracks are filed under their album artist, or their own artist on albums without one
+    pub fn artist_name(&self) -> &str {
+        return self
+            .album_artist
+            .as_deref()
+            .or(self.artist.as_deref())
+            .unwrap_or("Unknown artist");
+    }
+
+    pub fn album_name(&self) -> &str {
+        return self.album.as_deref().unwrap_or("Unknown album");
+    }
+
+    pub fn genre_name(&self) -> &str {
+        return self.genre.as_deref().unwrap_or("Unknown genre");
+    }
+
+    // Dates are anything from "1959" to "1959-08-17", the year is what they have in common
+    pub fn year(&self) -> Option<u32> {
+        let date = self.date.as_deref()?;
+        return date.get(..4)?.parse().ok();
+    }
+
+    pub fn playlist_entry(&self) -> PlaylistEntry {
+        PlaylistEntry {
+            path: PathBuf::from(&self.path),
+            title: None,
+            cue_track: self.cue_track.clone(),
+        }
+    }
+}
+
+// Every track found under the library roots, kept on disk between runs
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Library {
+    pub version: u32,
+
+    pub roots: Vec<PathBuf>,
+    pub tracks: Vec<LibraryTrack>,
+    // Every file under the roots, including the ones that turned out not to be playable,
+    // so that those aren't read again either
+    pub files: BTreeMap<String, FileStamp>,
+}
+
+impl Library {
+    pub fn new(roots: Vec<PathBuf>) -> Self {
+        Self {
+            version: LIBRARY_VERSION,
+
+            roots,
+            tracks: Vec::new(),
+            files: BTreeMap::new(),
+        }
+    }
+
+    // `GIGR_LIBRARY` if it's set, `~/Music` otherwise
+    pub fn configured_roots() -> Vec<PathBuf> {
+        match env::var(LIBRARY_ROOTS_VARIABLE) {
+            Ok(roots) if !roots.is_empty() => {
+                return roots
+                    .split(':')
+                    .filter(|root| !root.is_empty())
+                    .map(expand_tilde)
+                    .collect();
+            }
+            _ => return vec![expand_tilde(DEFAULT_LIBRARY_ROOT)],
+        }
+    }
+
+    fn file_path() -> PathBuf {
+        return xdg_dir("XDG_DATA_HOME", "~/.local/share").join(LIBRARY_FILE_NAME);
+    }
+
+    // Missing, unreadable or outdated indexes all mean scanning again
+    pub fn load() -> Option<Self> {
+        let contents = fs::read_to_string(Self::file_path()).ok()?;
+        let library: Library = serde_json::from_str(&contents).ok()?;
+
+        if library.version != LIBRARY_VERSION {
+            return None;
+        }
+
+        return Some(library);
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::file_path();
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string(self)?)?;
+
+        Ok(())
+    }
+
+    // Reads the tags of everything playable under the roots. Only files that are new or
+    // changed since `previous` was scanned are read, without it every file is.
+    // Directories and files that can't be read are left out, a library with a missing drive
+    // in it is still a library
+    pub fn scan(
+        roots: Vec<PathBuf>,
+        previous: Option<Library>,
+        mut progress: impl FnMut(ScanProgress),
+    ) -> Self {
+        let previous = previous.unwrap_or_else(|| Self::new(Vec::new()));
+
+        let mut library = Self::new(roots.clone());
+        library.add_dirs(&roots, previous, &mut progress);
+
+        return library;
+    }
+
+    // Scans the directories at or below `dir_paths` again, e.g. after the file watcher saw
+    // something change in them. The rest of the library stays as it is, and so do the tags
+    // of files in these directories that didn't change. Directories outside the roots are
+    // no part of the library and are ignored
+    pub fn rescan_dirs(
+        &mut self,
+        mut dir_paths: Vec<PathBuf>,
+        mut progress: impl FnMut(ScanProgress),
+    ) -> () {
+        dir_paths.retain(|dir_path| self.roots.iter().any(|root| dir_path.starts_with(root)));
+        dir_paths.sort();
+        dir_paths.dedup();
+        // Directories inside others get scanned along with those
+        let dir_paths: Vec<PathBuf> = dir_paths
+            .iter()
+            .filter(|dir_path| {
+                !dir_paths
+                    .iter()
+                    .any(|other| other != *dir_path && dir_path.starts_with(other))
+            })
+            .cloned()
+            .collect();
+
+        let is_below = |path: &str| {
+            dir_paths
+                .iter()
+                .any(|dir_path| Path::new(path).starts_with(dir_path))
+        };
+
+        let mut previous = Self::new(Vec::new());
+        (previous.tracks, self.tracks) = mem::take(&mut self.tracks)
+            .into_iter()
+            .partition(|track| is_below(&track.path));
+        (previous.files, self.files) = mem::take(&mut self.files)
+            .into_iter()
+            .partition(|(path, _)| is_below(path));
+
+        self.add_dirs(&dir_paths, previous, &mut progress);
+    }
+
+    // Adds what's in the directories and below them, taking the tags of files that didn't
+    // change from `previous` and reading the rest
+    fn add_dirs(
+        &mut self,
+        dir_paths: &[PathBuf],
+        previous: Library,
+        progress: &mut impl FnMut(ScanProgress),
+    ) -> () {
+        let mut dirs = Vec::new();
+        let mut found = 0;
+        for dir_path in dir_paths {
+            Self::list_dir(dir_path, &mut dirs, &mut found, progress);
+        }
+        progress(ScanProgress::Looking(found));
+
+        let mut previous_tracks: HashMap<String, Vec<LibraryTrack>> = HashMap::new();
+        for track in previous.tracks {
+            previous_tracks
+                .entry(track.path.clone())
+                .or_default()
+                .push(track);
+        }
+
+        let mut previous_dir_sizes: HashMap<PathBuf, usize> = HashMap::new();
+        let mut previous_cue_dirs: HashSet<PathBuf> = HashSet::new();
+        for path in previous.files.keys() {
+            if let Some(dir_path) = Path::new(path).parent() {
+                *previous_dir_sizes
+                    .entry(dir_path.to_path_buf())
+                    .or_default() += 1;
+
+                if is_cue_sheet(Path::new(path)) {
+                    previous_cue_dirs.insert(dir_path.to_path_buf());
+                }
+            }
+        }
+
+        let mut unread_dirs: Vec<Vec<PathBuf>> = Vec::new();
+
+        for dir in dirs {
+            let is_changed = |path: &PathBuf, stamp: &FileStamp| {
+                previous.files.get(&*path.to_string_lossy()) != Some(stamp)
+            };
+
+            let dir_changed = dir
+                .files
+                .iter()
+                .any(|(path, stamp)| is_changed(path, stamp))
+                || previous_dir_sizes.get(&dir.path) != Some(&dir.files.len());
+            // Which files a CUE sheet splits up depends on the sheet as well as the file,
+            // so directories with one are read again whole. So are directories that had one,
+            // their files aren't split up anymore
+            let read_all = dir_changed
+                && (previous_cue_dirs.contains(&dir.path)
+                    || dir.files.iter().any(|(path, _)| is_cue_sheet(path)));
+
+            let mut unread = Vec::new();
+            for (path, stamp) in dir.files {
+                let key = path.to_string_lossy().to_string();
+
+                if read_all || is_changed(&path, &stamp) {
+                    unread.push(path);
+                } else if let Some(tracks) = previous_tracks.remove(&key) {
+                    self.tracks.extend(tracks);
+                }
+
+                self.files.insert(key, stamp);
+            }
+
+            if !unread.is_empty() {
+                unread_dirs.push(unread);
+            }
+        }
+
+        let total = unread_dirs.iter().map(|files| files.len()).sum();
+        let mut read = 0;
+        progress(ScanProgress::Reading(read, total));
+
+        for files in unread_dirs {
+            read += files.len();
+
+            for entry in Playlist::from_paths(files).entries {
+                if let Ok(song) = SongLoader::load_entry(&entry) {
+                    self.tracks.push(LibraryTrack::from_song(&song));
+                }
+            }
+
+            progress(ScanProgress::Reading(read, total));
+        }
+    }
+
+    // Collects the files of the directory and everything below it, without reading them
+    fn list_dir(
+        dir_path: &Path,
+        dirs: &mut Vec<ScannedDir>,
+        found: &mut usize,
+        progress: &mut impl FnMut(ScanProgress),
+    ) -> () {
+        let Ok(entries) = read_dir(dir_path) else {
+            return;
+        };
+
+        let mut entries: Vec<_> = entries.filter_map(|res| res.ok()).collect();
+        entries.sort_by_key(|entry| entry.path());
+
+        let mut files = Vec::new();
+        for entry in entries {
+            let path = entry.path();
+
+            // Symlinked directories aren't followed, they could lead back up the tree
+            match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() => {
+                    Self::list_dir(&path, dirs, found, progress);
+                }
+                _ => {
+                    if let Ok(metadata) = fs::metadata(&path)
+                        && metadata.is_file()
+                    {
+                        files.push((path, FileStamp::of(&metadata)));
+
+                        *found += 1;
+                        if found.is_multiple_of(PROGRESS_INTERVAL) {
+                            progress(ScanProgress::Looking(*found));
+                        }
+                    }
+                }
+            }
+        }
+
+        dirs.push(ScannedDir {
+            path: dir_path.to_path_buf(),
+            files,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::File;
+    use std::time::{Duration, SystemTime};
+
+    use crate::testing::{test_dir, write_wav};
+
+    fn write_cue(path: &Path, file: &str) -> () {
+        let sheet = format!(
+            "FILE \"{}\" WAVE\n  TRACK 01 AUDIO\n    TITLE \"One\"\n    INDEX 01 00:00:00\n  \
+             TRACK 02 AUDIO\n    TITLE \"Two\"\n    INDEX 01 00:00:40\n",
+            file
+        );
+        fs::write(path, sheet).unwrap();
+    }
+
+    // Changes the modification time, but not the contents
+    fn touch(path: &Path) -> () {
+        let file = File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+    }
+
+    // The library along with how many files had their tags read
+    fn scan(root: &Path, previous: Option<Library>) -> (Library, usize) {
+        let mut read = 0;
+        let library = Library::scan(vec![root.to_path_buf()], previous, |progress| {
+            if let ScanProgress::Reading(_, total) = progress {
+                read = total;
+            }
+        });
+
+        return (library, read);
+    }
+
+    fn rescan_dirs(library: &mut Library, dir_paths: Vec<PathBuf>) -> usize {
+        let mut read = 0;
+        library.rescan_dirs(dir_paths, |progress| {
+            if let ScanProgress::Reading(_, total) = progress {
+                read = total;
+            }
+        });
+
+        return read;
+    }
+
+    // Titles of tracks whose tags were read get replaced, so that tracks that are read
+    // again can be told apart from the ones taken from the index
+    fn mark_as_indexed(library: &mut Library) -> () {
+        for track in &mut library.tracks {
+            track.title = "indexed".to_string();
+        }
+    }
+
+    // File names and titles of the tracks, sorted
+    fn tracks(library: &Library) -> Vec<(String, String)> {
+        let mut tracks: Vec<(String, String)> = library
+            .tracks
+            .iter()
+            .map(|track| {
+                let name = Path::new(&track.path).file_name().unwrap();
+                (name.to_string_lossy().to_string(), track.title.clone())
+            })
+            .collect();
+        tracks.sort();
+
+        return tracks;
+    }
+
+    fn track(name: &str, title: &str) -> (String, String) {
+        return (name.to_string(), title.to_string());
+    }
+
+    #[test]
+    fn unchanged_files_are_taken_from_the_index() {
+        let root = test_dir("library-unchanged");
+        write_wav(&root.join("a.wav"));
+        write_wav(&root.join("b.wav"));
+        fs::write(root.join("cover.jpg"), "not an image").unwrap();
+
+        let (mut library, read) = scan(&root, None);
+        assert_eq!(read, 3);
+        assert_eq!(library.tracks.len(), 2);
+        // Files that can't be played are remembered too, so that they aren't read again
+        assert_eq!(library.files.len(), 3);
+
+        mark_as_indexed(&mut library);
+        let (library, read) = scan(&root, Some(library));
+        assert_eq!(read, 0);
+        assert_eq!(
+            tracks(&library),
+            vec![track("a.wav", "indexed"), track("b.wav", "indexed")]
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn changed_new_and_deleted_files_are_picked_up() {
+        let root = test_dir("library-changes");
+        write_wav(&root.join("a.wav"));
+        write_wav(&root.join("b.wav"));
+        write_wav(&root.join("c.wav"));
+        fs::write(root.join("cover.jpg"), "not an image").unwrap();
+
+        let (mut library, _) = scan(&root, None);
+        mark_as_indexed(&mut library);
+
+        touch(&root.join("a.wav"));
+        fs::remove_file(root.join("b.wav")).unwrap();
+        write_wav(&root.join("d.wav"));
+
+        let (library, read) = scan(&root, Some(library));
+        assert_eq!(read, 2);
+        assert_eq!(
+            tracks(&library),
+            vec![
+                track("a.wav", "a"),
+                track("c.wav", "indexed"),
+                track("d.wav", "d")
+            ]
+        );
+        assert!(
+            !library
+                .files
+                .contains_key(&*root.join("b.wav").to_string_lossy())
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn directories_with_a_cue_sheet_are_read_whole() {
+        let root = test_dir("library-cue");
+        write_wav(&root.join("album/album.wav"));
+        write_cue(&root.join("album/album.cue"), "album.wav");
+        write_wav(&root.join("single/single.wav"));
+
+        let (mut library, _) = scan(&root, None);
+        assert_eq!(library.tracks.len(), 3);
+        mark_as_indexed(&mut library);
+
+        // The file the sheet splits up is read again with it, the other directory isn't
+        touch(&root.join("album/album.cue"));
+        let (mut library, read) = scan(&root, Some(library));
+        assert_eq!(read, 2);
+        assert_eq!(
+            tracks(&library),
+            vec![
+                track("album.wav", "One"),
+                track("album.wav", "Two"),
+                track("single.wav", "indexed"),
+            ]
+        );
+
+        // Without the sheet the file is a single track again
+        mark_as_indexed(&mut library);
+        fs::remove_file(root.join("album/album.cue")).unwrap();
+        let (library, read) = scan(&root, Some(library));
+        assert_eq!(read, 1);
+        assert_eq!(
+            tracks(&library),
+            vec![track("album.wav", "album"), track("single.wav", "indexed")]
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    // Two albums of one artist below the root, plus a directory outside of it
+    fn artist_dir(name: &str) -> (PathBuf, Library) {
+        let dir = test_dir(&format!("library-{}", name));
+        let root = dir.join("root");
+        write_wav(&root.join("Artist/First/a.wav"));
+        write_wav(&root.join("Artist/Second/b.wav"));
+        write_wav(&dir.join("outside/c.wav"));
+
+        let (mut library, _) = scan(&root, None);
+        mark_as_indexed(&mut library);
+
+        return (dir, library);
+    }
+
+    #[test]
+    fn nested_directories_are_only_scanned_once() {
+        let (dir, mut library) = artist_dir("nested");
+        let artist = dir.join("root/Artist");
+        write_wav(&artist.join("First/d.wav"));
+
+        let read = rescan_dirs(&mut library, vec![artist.join("First"), artist.clone()]);
+        assert_eq!(read, 1);
+        assert_eq!(
+            tracks(&library),
+            vec![
+                track("a.wav", "indexed"),
+                track("b.wav", "indexed"),
+                track("d.wav", "d"),
+            ]
+        );
+        assert_eq!(library.files.len(), 3);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn deleted_directories_leave_the_library() {
+        let (dir, mut library) = artist_dir("deleted");
+        let artist = dir.join("root/Artist");
+        fs::remove_dir_all(artist.join("Second")).unwrap();
+
+        let read = rescan_dirs(&mut library, vec![artist.join("Second")]);
+        assert_eq!(read, 0);
+        assert_eq!(tracks(&library), vec![track("a.wav", "indexed")]);
+        assert_eq!(library.files.len(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn directories_outside_the_roots_are_ignored() {
+        let (dir, mut library) = artist_dir("outside");
+        write_wav(&dir.join("outside/e.wav"));
+
+        let read = rescan_dirs(&mut library, vec![dir.join("outside"), dir.clone()]);
+        assert_eq!(read, 0);
+        assert_eq!(
+            tracks(&library),
+            vec![track("a.wav", "indexed"), track("b.wav", "indexed")]
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
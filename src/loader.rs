@@ -0,0 +1,400 @@
+use std::path::PathBuf;
+use std::sync::mpsc::{Receiver, Sender, TryRecvError, channel};
+use std::thread;
+
+use color_eyre::eyre::eyre;
+use color_eyre::{Report, Result};
+
+use image::DynamicImage;
+use image::imageops::FilterType;
+
+use crate::cover::load_cover;
+use crate::formats::{AudioSource, open_song};
+use crate::library::{Library, ScanProgress};
+use crate::playlist::{Playlist, PlaylistEntry};
+use crate::search::{SearchItem, items_in_tree};
+use crate::shuffle::PlayOrder;
+use crate::song::Song;
+
+// Largest size cover art gets scaled down to before it's shown
+const COVER_SIZE: u32 = 600;
+
+// Work that reads files happens on worker threads, which hand their results back through
+// channels. The receiving side polls them, so that drawing never waits for the disk.
+// Dropping a receiver abandons the work, a worker notices once it can't send anymore
+
+// Opens the song for playback
+pub fn open_in_background(song: Song) -> Receiver<Result<AudioSource>> {
+    let (sender, receiver) = channel();
+
+    thread::spawn(move || {
+        let _ = sender.send(open_song(&song));
+    });
+
+    return receiver;
+}
+
+// Finds and scales the cover art of the song at `song_path`
+pub fn load_cover_in_background(song_path: PathBuf) -> Receiver<Result<Option<DynamicImage>>> {
+    let (sender, receiver) = channel();
+
+    thread::spawn(move || {
+        let cover = load_cover(&song_path).map(|cover| {
+            cover.map(|image| image.resize(COVER_SIZE, COVER_SIZE, FilterType::Gaussian))
+        });
+        let _ = sender.send(cover);
+    });
+
+    return receiver;
+}
+
+pub enum LibraryUpdate {
+    // The index as it was last saved, to browse while it's brought up to date
+    Loaded(Library),
+    Progress(ScanProgress),
+    Scanned(Library),
+    // Sent after the scanned library if it couldn't be saved
+    Failed(Report),
+}
+
+// Brings the library index up to date with the files under the roots and saves it.
+// A rebuild reads every file again instead of only the new and changed ones
+pub fn scan_library_in_background(rebuild: bool) -> Receiver<LibraryUpdate> {
+    let (sender, receiver) = channel();
+
+    thread::spawn(move || {
+        let previous = Library::load();
+        if let Some(previous) = &previous
+            && sender
+                .send(LibraryUpdate::Loaded(previous.clone()))
+                .is_err()
+        {
+            return;
+        }
+
+        let previous = match rebuild {
+            true => None,
+            false => previous,
+        };
+        let library = Library::scan(Library::configured_roots(), previous, |progress| {
+            let _ = sender.send(LibraryUpdate::Progress(progress));
+        });
+
+        let saved = library.save();
+        let _ = sender.send(LibraryUpdate::Scanned(library));
+        if let Err(error) = saved {
+            let _ = sender.send(LibraryUpdate::Failed(error));
+        }
+    });
+
+    return receiver;
+}
+
+// Scans the directories in which the file watcher saw changes again and saves the library.
+// `library` is the index as it is being browsed, the result replaces it
+pub fn rescan_dirs_in_background(
+    mut library: Library,
+    dir_paths: Vec<PathBuf>,
+) -> Receiver<LibraryUpdate> {
+    let (sender, receiver) = channel();
+
+    thread::spawn(move || {
+        library.rescan_dirs(dir_paths, |progress| {
+            let _ = sender.send(LibraryUpdate::Progress(progress));
+        });
+
+        let saved = library.save();
+        let _ = sender.send(LibraryUpdate::Scanned(library));
+        if let Err(error) = saved {
+            let _ = sender.send(LibraryUpdate::Failed(error));
+        }
+    });
+
+    return receiver;
+}
+
+// Finds what the search can offer below a directory, for when there's no library
+pub fn list_tree_in_background(dir_path: PathBuf) -> Receiver<Result<Vec<SearchItem>>> {
+    let (sender, receiver) = channel();
+
+    thread::spawn(move || {
+        let _ = sender.send(Ok(items_in_tree(&dir_path)));
+    });
+
+    return receiver;
+}
+
+// Whatever a worker sent, `None` while it's still busy
+pub fn poll<T>(receiver: &Receiver<Result<T>>) -> Option<Result<T>> {
+    match receiver.try_recv() {
+        Ok(result) => return Some(result),
+        Err(TryRecvError::Empty) => return None,
+        Err(TryRecvError::Disconnected) => return Some(Err(eyre!("the loading thread stopped"))),
+    }
+}
+
+// Where songs go in the queue once they're loaded
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Placement {
+    End,
+    // Right after the current song
+    Next,
+    // In place of the current song
+    Now,
+}
+
+// What to do with a song that was just added to the end of the queue
+#[derive(PartialEq, Debug)]
+pub enum PlacementStep {
+    Stay,
+    // Move it from the first position in the play order to the second
+    Move(usize, usize),
+    // Start playing it at this position
+    PlayNow(usize),
+}
+
+// Places the songs of one playlist as they come in. Only the first song goes where the
+// placement says, every other song follows the one before it, so that the playlist stays
+// together and in order
+pub struct PlaylistPlacement {
+    placement: Placement,
+    // Queue index of the song of the playlist that was placed last
+    previous: Option<usize>,
+}
+
+impl PlaylistPlacement {
+    pub fn new(placement: Placement) -> Self {
+        Self {
+            placement,
+            previous: None,
+        }
+    }
+
+    // `order` already holds the song, `upcoming` is the position of the track
+    // that plays after the current one
+    pub fn place(&mut self, order: &PlayOrder, upcoming: usize) -> PlacementStep {
+        let Some(queue_index) = order.as_slice().len().checked_sub(1) else {
+            return PlacementStep::Stay;
+        };
+        let Some(from) = order.position_of(queue_index) else {
+            return PlacementStep::Stay;
+        };
+
+        let previous = self.previous.replace(queue_index);
+
+        // Moving an entry back takes it out in front of where it goes
+        let behind = |position: usize| match from <= position {
+            true => position,
+            false => position + 1,
+        };
+
+        match (self.placement, previous) {
+            (Placement::End, _) => return PlacementStep::Stay,
+            (Placement::Next, None) => {
+                let to = match from < upcoming {
+                    true => upcoming - 1,
+                    false => upcoming,
+                };
+                return PlacementStep::Move(from, to);
+            }
+            (Placement::Now, None) => return PlacementStep::PlayNow(from),
+            (_, Some(previous)) => match order.position_of(previous) {
+                Some(position) => return PlacementStep::Move(from, behind(position)),
+                // Removed from the queue while the rest was loading
+                None => return PlacementStep::Stay,
+            },
+        }
+    }
+}
+
+pub enum SongLoad {
+    Loaded(Box<Song>, Placement),
+    // Sent after the last song of a playlist, with the entries that couldn't be read
+    Finished(Vec<String>),
+}
+
+// Reads the tags of the songs in playlists, one playlist after the other so that
+// the queue gets them in the order they were added
+pub struct SongLoader {
+    requests: Sender<(Playlist, Placement)>,
+    results: Receiver<SongLoad>,
+    // Playlists that were handed over but aren't finished yet
+    pending: usize,
+}
+
+impl SongLoader {
+    pub fn new() -> Self {
+        let (requests, request_receiver) = channel::<(Playlist, Placement)>();
+        let (result_sender, results) = channel();
+
+        thread::spawn(move || {
+            for (playlist, placement) in request_receiver {
+                let mut skipped = playlist.unresolved.clone();
+
+                for entry in &playlist.entries {
+                    match Self::load_entry(entry) {
+                        Ok(song) => {
+                            if result_sender
+                                .send(SongLoad::Loaded(Box::new(song), placement))
+                                .is_err()
+                            {
+                                return;
+                            }
+                        }
+                        Err(error) => {
+                            skipped.push(format!("{} ({})", entry.path.display(), error));
+                        }
+                    }
+                }
+
+                if result_sender.send(SongLoad::Finished(skipped)).is_err() {
+                    return;
+                }
+            }
+        });
+
+        Self {
+            requests,
+            results,
+            pending: 0,
+        }
+    }
+
+    pub fn load_entry(entry: &PlaylistEntry) -> Result<Song> {
+        let mut song = Song::new(&entry.path.to_string_lossy())?;
+
+        if let Some(track) = &entry.cue_track {
+            song.set_cue_track(track.clone());
+        }
+
+        // Untagged files go by the title the playlist gave them
+        if song.title.is_none()
+            && let Some(title) = &entry.title
+        {
+            match title.split_once(" - ") {
+                Some((artist, title)) => {
+                    song.artist = Some(artist.to_string());
+                    song.title = Some(title.to_string());
+                }
+                None => song.title = Some(title.clone()),
+            }
+        }
+
+        return Ok(song);
+    }
+
+    pub fn load(&mut self, playlist: Playlist, placement: Placement) -> () {
+        if self.requests.send((playlist, placement)).is_ok() {
+            self.pending += 1;
+        }
+    }
+
+    // The next song or finished playlist, if the worker got to one
+    pub fn try_next(&mut self) -> Option<SongLoad> {
+        let load = self.results.try_recv().ok()?;
+
+        if let SongLoad::Finished(_) = load {
+            self.pending -= 1;
+        }
+
+        return Some(load);
+    }
+
+    pub fn loading(&self) -> bool {
+        return self.pending > 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Adds a playlist of `songs` songs the way the app does, `upcoming` follows the
+    // current track like the player's index does
+    fn load_playlist(
+        order: &mut PlayOrder,
+        upcoming: &mut usize,
+        placement: Placement,
+        songs: usize,
+    ) -> () {
+        let mut playlist_placement = PlaylistPlacement::new(placement);
+
+        for _ in 0..songs {
+            order.push(*upcoming);
+
+            match playlist_placement.place(order, *upcoming) {
+                PlacementStep::Stay => {}
+                PlacementStep::Move(from, to) => order.move_entry(from, to),
+                PlacementStep::PlayNow(position) => *upcoming = position + 1,
+            }
+        }
+    }
+
+    fn queue(songs: usize, seed: u64) -> PlayOrder {
+        let mut order = PlayOrder::new(Some(seed));
+        for _ in 0..songs {
+            order.push(0);
+        }
+
+        return order;
+    }
+
+    #[test]
+    fn next_keeps_the_playlist_in_order() {
+        let mut order = queue(3, 1);
+        let mut upcoming = 1;
+
+        load_playlist(&mut order, &mut upcoming, Placement::Next, 3);
+
+        assert_eq!(order.as_slice(), [0, 3, 4, 5, 1, 2]);
+        assert_eq!(upcoming, 1);
+    }
+
+    #[test]
+    fn next_keeps_the_playlist_together_while_shuffled() {
+        let mut order = queue(5, 7);
+        order.shuffle(1);
+        let mut upcoming = 1;
+
+        load_playlist(&mut order, &mut upcoming, Placement::Next, 3);
+
+        assert_eq!(order.as_slice()[1..4], [5, 6, 7]);
+        assert_eq!(order.as_slice().len(), 8);
+    }
+
+    #[test]
+    fn now_only_plays_the_first_song() {
+        let mut order = queue(5, 7);
+        order.shuffle(1);
+        let mut upcoming = 1;
+
+        let mut playlist_placement = PlaylistPlacement::new(Placement::Now);
+        order.push(upcoming);
+        let first = order.position_of(5).unwrap();
+        assert_eq!(
+            playlist_placement.place(&order, upcoming),
+            PlacementStep::PlayNow(first)
+        );
+        upcoming = first + 1;
+
+        for _ in 0..2 {
+            order.push(upcoming);
+            match playlist_placement.place(&order, upcoming) {
+                PlacementStep::Move(from, to) => order.move_entry(from, to),
+                step => panic!("expected a move, got {:?}", step),
+            }
+        }
+
+        assert_eq!(order.as_slice()[first..first + 3], [5, 6, 7]);
+    }
+
+    #[test]
+    fn end_leaves_songs_where_they_were_added() {
+        let mut order = queue(3, 1);
+        let mut upcoming = 1;
+
+        load_playlist(&mut order, &mut upcoming, Placement::End, 3);
+
+        assert_eq!(order.as_slice(), [0, 1, 2, 3, 4, 5]);
+    }
+}
@@ -1,33 +1,74 @@
+#![allow(clippy::needless_return, clippy::unused_unit)]
+
 use std::env;
+use std::io::stdout;
 
 use std::path::PathBuf;
 
 use color_eyre::Result;
 
+use crossterm::event::{DisableMouseCapture, EnableMouseCapture};
+use crossterm::execute;
+
 // Modules
 mod app;
+mod browser;
+mod cover;
+mod cue;
 mod files;
+mod formats;
+mod library;
+mod loader;
+#[cfg(feature = "opus")]
+mod opus;
 mod player;
+mod playlist;
+mod plays;
+mod query;
+mod search;
+mod shuffle;
+mod smart;
 mod song;
+mod spectrum;
+mod state;
+mod tap;
+#[cfg(test)]
+mod testing;
+mod visualizer;
+mod watcher;
 
 use crate::app::App;
 
 fn main() -> Result<()> {
     color_eyre::install()?;
 
+    // Reads every file of the library again instead of only the ones that changed
+    let rescan = env::args().skip(1).any(|arg| arg == "--rescan");
+
     let mut terminal = ratatui::init();
-    let app_result = App::new().run(&mut terminal);
+    execute!(stdout(), EnableMouseCapture)?;
+    // The terminal gets restored whether or not gigr could start
+    let app_result = App::new(rescan).and_then(|mut app| app.run(&mut terminal));
+    execute!(stdout(), DisableMouseCapture)?;
     ratatui::restore();
     app_result
 }
 
 fn expand_tilde(path: &str) -> PathBuf {
-    if path.starts_with("~/") {
-        if let Some(home) = env::var_os("HOME") {
-            let mut p = PathBuf::from(home);
-            p.push(&path[2..]);
-            return p;
-        }
+    if path.starts_with("~/")
+        && let Some(home) = env::var_os("HOME")
+    {
+        let mut p = PathBuf::from(home);
+        p.push(&path[2..]);
+        return p;
     }
     PathBuf::from(path)
 }
+
+// gigr's directory inside an XDG base directory, e.g. `~/.local/state/gigr`
+fn xdg_dir(variable: &str, fallback: &str) -> PathBuf {
+    match env::var_os(variable) {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join("gigr"),
+        _ => expand_tilde(fallback).join("gigr"),
+    }
+}
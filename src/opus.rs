@@ -0,0 +1,236 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::time::Duration;
+
+use audiopus::coder::{Decoder, GenericCtl};
+use audiopus::packet::Packet as OpusPacket;
+use audiopus::{Channels, MutSignals, SampleRate};
+
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+
+use ogg::{Packet, PacketReader};
+
+use rodio::Source;
+use rodio::source::SeekError;
+
+// Opus always decodes to 48kHz, granule positions count samples at that rate as well
+const OPUS_SAMPLE_RATE: u32 = 48000;
+// The longest a single Opus packet can be, 120ms
+const MAX_PACKET_SAMPLES: usize = 5760;
+// The decoder needs some audio before the seek target to converge again
+const SEEK_PREROLL: u64 = 3840;
+// How much of the end of the file is searched for the last granule position
+const TAIL_SEARCH_LEN: u64 = 64 * 1024;
+
+// Ogg Opus decoding through libopus, the symphonia decoders that rodio uses have no Opus support
+pub struct OpusDecoder {
+    reader: PacketReader<BufReader<File>>,
+    decoder: Decoder,
+
+    channels: u16,
+    // Samples (per channel) at the start of the stream that are only there to prime the decoder
+    pre_skip: u64,
+    output_gain: f32,
+    total_duration: Option<Duration>,
+
+    // Packets that were read ahead while seeking
+    pending: VecDeque<Packet>,
+    // Samples (per channel) to drop before the next one gets played
+    skip: u64,
+
+    buffer: Vec<f32>,
+    buffer_pos: usize,
+}
+
+impl OpusDecoder {
+    pub fn new(mut file: File) -> Result<Self> {
+        let last_granule = Self::last_granule(&mut file);
+        file.seek(SeekFrom::Start(0))?;
+
+        let mut reader = PacketReader::new(BufReader::new(file));
+
+        // The identification header, see RFC 7845 section 5.1
+        let head = reader.read_packet_expected()?;
+        if head.data.len() < 19 || !head.data.starts_with(b"OpusHead") {
+            return Err(eyre!("not an Ogg Opus stream"));
+        }
+
+        let channels = match (head.data[9], head.data[18]) {
+            (1, 0) => Channels::Mono,
+            (2, 0) => Channels::Stereo,
+            (count, _) => {
+                return Err(eyre!(
+                    "Opus streams with {} channels aren't supported",
+                    count
+                ));
+            }
+        };
+        let pre_skip = u16::from_le_bytes([head.data[10], head.data[11]]) as u64;
+        // A Q7.8 gain in dB that has to be applied to the decoded output
+        let output_gain_db = i16::from_le_bytes([head.data[16], head.data[17]]) as f32 / 256.0;
+
+        // The comment header carries the tags, which lofty already reads
+        reader.read_packet_expected()?;
+
+        let total_duration = last_granule.map(|granule| {
+            Duration::from_secs_f64(
+                granule.saturating_sub(pre_skip) as f64 / OPUS_SAMPLE_RATE as f64,
+            )
+        });
+
+        return Ok(Self {
+            reader,
+            decoder: Decoder::new(SampleRate::Hz48000, channels)?,
+
+            channels: channels as u16,
+            pre_skip,
+            output_gain: 10f32.powf(output_gain_db / 20.0),
+            total_duration,
+
+            pending: VecDeque::new(),
+            skip: pre_skip,
+
+            buffer: Vec::new(),
+            buffer_pos: 0,
+        });
+    }
+
+    // The granule position of the last page tells how many samples the stream holds
+    fn last_granule(file: &mut File) -> Option<u64> {
+        let len = file.metadata().ok()?.len();
+        file.seek(SeekFrom::Start(len.saturating_sub(TAIL_SEARCH_LEN)))
+            .ok()?;
+
+        let mut tail: Vec<u8> = Vec::new();
+        file.read_to_end(&mut tail).ok()?;
+
+        // Pages on which no packet ends have a granule position of -1
+        return tail
+            .windows(14)
+            .rev()
+            .filter(|window| window.starts_with(b"OggS"))
+            .map(|window| u64::from_le_bytes(window[6..14].try_into().unwrap()))
+            .find(|granule| *granule != u64::MAX);
+    }
+
+    fn next_packet(&mut self) -> Option<Packet> {
+        if let Some(packet) = self.pending.pop_front() {
+            return Some(packet);
+        }
+
+        return self.reader.read_packet().ok().flatten();
+    }
+
+    // Decodes the next packet into the buffer, false once the stream has ended
+    fn decode_next(&mut self) -> bool {
+        let Some(packet) = self.next_packet() else {
+            return false;
+        };
+
+        let channels = self.channels as usize;
+        self.buffer.resize(MAX_PACKET_SAMPLES * channels, 0.0);
+        self.buffer_pos = 0;
+
+        let decoded = OpusPacket::try_from(&packet.data).and_then(|input| {
+            let output = MutSignals::try_from(&mut self.buffer)?;
+            self.decoder.decode_float(Some(input), output, false)
+        });
+
+        // A broken packet is skipped rather than ending the track
+        let samples = decoded.unwrap_or(0);
+        self.buffer.truncate(samples * channels);
+
+        let skipped = self.skip.min(samples as u64);
+        self.skip -= skipped;
+        self.buffer_pos = skipped as usize * channels;
+
+        if self.output_gain != 1.0 {
+            for sample in self.buffer.iter_mut() {
+                *sample *= self.output_gain;
+            }
+        }
+
+        return true;
+    }
+
+    // Seeks to the page holding `granule` and returns the granule position at which that page
+    // starts, working it out from the lengths of the packets that end on it
+    fn seek_to_page(&mut self, granule: u64) -> Option<u64> {
+        if !self.reader.seek_absgp(None, granule).ok()? {
+            return None;
+        }
+
+        let mut page_samples = 0;
+        loop {
+            let packet = self.reader.read_packet().ok()??;
+
+            if let Ok(input) = OpusPacket::try_from(&packet.data) {
+                page_samples += self.decoder.nb_samples(input).unwrap_or(0) as u64;
+            }
+
+            let page_end = packet.absgp_page();
+            let last_in_page = packet.last_in_page();
+            self.pending.push_back(packet);
+
+            if last_in_page {
+                return Some(page_end.saturating_sub(page_samples));
+            }
+        }
+    }
+}
+
+impl Iterator for OpusDecoder {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        while self.buffer_pos >= self.buffer.len() {
+            if !self.decode_next() {
+                return None;
+            }
+        }
+
+        let sample = self.buffer[self.buffer_pos];
+        self.buffer_pos += 1;
+
+        return Some(sample);
+    }
+}
+
+impl Source for OpusDecoder {
+    fn current_span_len(&self) -> Option<usize> {
+        return None;
+    }
+
+    fn channels(&self) -> u16 {
+        return self.channels;
+    }
+
+    fn sample_rate(&self) -> u32 {
+        return OPUS_SAMPLE_RATE;
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        return self.total_duration;
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        let target = (pos.as_secs_f64() * OPUS_SAMPLE_RATE as f64) as u64 + self.pre_skip;
+
+        self.pending.clear();
+        self.buffer.clear();
+        self.buffer_pos = 0;
+        let _ = self.decoder.reset_state();
+
+        let page_start = self
+            .seek_to_page(target.saturating_sub(SEEK_PREROLL).max(self.pre_skip))
+            .ok_or(SeekError::NotSupported {
+                underlying_source: "OpusDecoder",
+            })?;
+
+        self.skip = target.saturating_sub(page_start);
+
+        Ok(())
+    }
+}
@@ -1,100 +1,652 @@
-use std::io::BufReader;
+use std::collections::HashSet;
+use std::env;
+use std::path::PathBuf;
 
-use std::fs::File;
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use rodio::Decoder;
+use color_eyre::{Report, Result};
+
 use rodio::OutputStream;
 use rodio::Sink;
 
 use rodio::Source;
-use rustfft::{FftPlanner, num_complex::Complex};
+use rodio::mixer::mixer;
+use rodio::source::Zero;
+
+use serde::{Deserialize, Serialize};
 
-use crate::expand_tilde;
+use crate::formats::AudioSource;
+use crate::loader::{open_in_background, poll};
+use crate::shuffle::PlayOrder;
 use crate::song::Song;
+use crate::spectrum::{Spectrum, SpectrumFrame};
+use crate::state::{SavedEntry, SavedState, restore_queue};
+use crate::tap::{SampleRing, Tap};
 
+// Frames the visualizer looks at, about 43ms at 48kHz
 const EQ_BUFFER_SIZE: usize = 2048;
 
+// How long before the end of the current track the next one gets appended to the sink
+const PRELOAD_WINDOW: Duration = Duration::from_secs(10);
+
+// How often the gain of a fading track gets recalculated
+const FADE_UPDATE_PERIOD: Duration = Duration::from_millis(5);
+
+// Crossfade lengths cycled through by `cycle_crossfade`, `None` meaning hard cuts
+const CROSSFADE_STEPS: [Option<Duration>; 4] = [
+    None,
+    Some(Duration::from_secs(2)),
+    Some(Duration::from_secs(5)),
+    Some(Duration::from_secs(10)),
+];
+
+// A track that was appended to the sink, along with what is shown while it plays.
+// It's kept by queue index, as tracks added while shuffled can shift the play order
+struct LoadedSong {
+    queue_index: usize,
+    song: Song,
+    duration: Option<Duration>,
+    fade_out: FadeOut,
+    replay_gain: Arc<Mutex<f32>>,
+}
+
+// What a track that is being opened does once it's ready
+#[derive(PartialEq)]
+enum LoadKind {
+    // Takes over the empty sink
+    Start,
+    // Waits in the sink behind the current track
+    Preload,
+    // Fades in while the previous track fades out on the fading sink
+    Crossfade(Duration),
+}
+
+// A track being opened on a worker thread
+struct PendingLoad {
+    queue_index: usize,
+    kind: LoadKind,
+    source: Receiver<Result<AudioSource>>,
+}
+
+// Lets the player start fading out a track that is already playing.
+// Holds the position inside the track at which the fade starts and how long it lasts
+#[derive(Clone, Default)]
+struct FadeOut(Arc<Mutex<Option<(Duration, Duration)>>>);
+
+impl FadeOut {
+    fn start(&self, from: Duration, length: Duration) -> () {
+        *self.0.lock().unwrap() = Some((from, length));
+    }
+
+    // Silences the track right from its start
+    fn cut(&self) -> () {
+        self.start(Duration::ZERO, Duration::ZERO);
+    }
+
+    // Gain at `position`, or `None` once the track is fully faded out
+    fn gain(&self, position: Duration) -> Option<f32> {
+        match *self.0.lock().unwrap() {
+            Some((from, length)) => {
+                if position >= from + length {
+                    return None;
+                }
+
+                let faded = position.saturating_sub(from).as_secs_f32() / length.as_secs_f32();
+                return Some(1.0 - faded);
+            }
+            None => return Some(1.0),
+        }
+    }
+}
+
 pub struct Player {
-    stream_handle: OutputStream,
+    _stream_handle: OutputStream,
     sink: Sink,
+    // Plays out the end of the previous track while a crossfade is in progress
+    fading_sink: Sink,
 
     queue: Vec<Song>,
+    // Goes up whenever songs are added to or removed from the queue,
+    // so that what depends on them is only worked out again after a change
+    queue_changes: u64,
+    // Paths of queue entries whose file was deleted after they were added
+    missing_files: HashSet<String>,
+    play_order: PlayOrder,
+    // Position of the current track in the play order, counting from 1
     pub player_index: usize,
 
     current_song: Option<Song>,
 
     volume: f32,
 
-    fft_planner: FftPlanner<f32>,
+    spectrum: Spectrum,
 
-    decoder: Option<Vec<f32>>,
+    // What was just played, with both tracks of a crossfade mixed together
+    played_samples: Arc<SampleRing>,
 
     current_song_duration: Option<Duration>,
+
+    preloaded_song: Option<LoadedSong>,
+    pending_load: Option<PendingLoad>,
+
+    crossfade: Option<Duration>,
+    current_fade_out: FadeOut,
+
+    replay_gain_mode: ReplayGainMode,
+    current_replay_gain: Arc<Mutex<f32>>,
+
+    repeat_mode: RepeatMode,
+    stop_after_current: bool,
+    // Set once playback stopped on purpose, the queue doesn't advance until playback is resumed
+    stopped: bool,
+    // Position that a skip asked for, started once the sink ran empty
+    requested_position: Option<usize>,
+    // Where to continue inside the requested track, used when restoring a saved state
+    resume_offset: Option<Duration>,
+    // Set when the next track already failed to open while preloading,
+    // it is then only tried again once the current one has ended
+    preload_failed: bool,
+
+    // The last thing that went wrong, for the UI to show
+    error: Option<String>,
 }
 
 impl Player {
-    pub fn new() -> Self {
-        let stream_handle =
-            rodio::OutputStreamBuilder::open_default_stream().expect("open default audio stream");
-        let sink = rodio::Sink::connect_new(&stream_handle.mixer());
+    pub fn new() -> Result<Self> {
+        let stream_handle = rodio::OutputStreamBuilder::open_default_stream()?;
+        let channels = stream_handle.config().channel_count();
+        let sample_rate = stream_handle.config().sample_rate();
+
+        // The sinks play into a mixer of their own, whose output gets tapped for the visualizer.
+        // Silence keeps that mixer going while nothing plays, it would end otherwise
+        let (sink_mixer, mixed) = mixer(channels, sample_rate);
+        sink_mixer.add(Zero::new(channels, sample_rate));
+
+        let played_samples = SampleRing::new(channels, sample_rate);
+        stream_handle
+            .mixer()
+            .add(Tap::new(mixed, played_samples.clone()));
 
-        Self {
-            sink: sink,
-            stream_handle: stream_handle,
+        let sink = rodio::Sink::connect_new(&sink_mixer);
+        let fading_sink = rodio::Sink::connect_new(&sink_mixer);
+
+        Ok(Self {
+            sink,
+            fading_sink,
+            _stream_handle: stream_handle,
 
             queue: Vec::new(),
+            queue_changes: 0,
+            missing_files: HashSet::new(),
+            play_order: PlayOrder::new(
+                env::var("GIGR_SHUFFLE_SEED")
+                    .ok()
+                    .and_then(|seed| seed.parse().ok()),
+            ),
             player_index: 0,
 
             current_song: None,
 
             volume: 1.0,
 
-            fft_planner: FftPlanner::new(),
+            spectrum: Spectrum::new(),
 
-            decoder: None,
+            played_samples,
 
             current_song_duration: None,
-        }
+
+            preloaded_song: None,
+            pending_load: None,
+
+            crossfade: None,
+            current_fade_out: FadeOut::default(),
+
+            replay_gain_mode: ReplayGainMode::Off,
+            current_replay_gain: Arc::new(Mutex::new(1.0)),
+
+            repeat_mode: RepeatMode::Off,
+            stop_after_current: false,
+            stopped: false,
+            requested_position: None,
+            resume_offset: None,
+            preload_failed: false,
+
+            error: None,
+        })
     }
 
     pub fn add_to_queue(&mut self, song: Song) -> () {
         self.queue.push(song);
+        self.queue_changes += 1;
+        self.play_order.push(self.player_index);
     }
 
+    // Moves playback along, returns whether another track took over.
+    // Opening tracks happens in the background, so this never waits for the disk
     pub fn update(&mut self) -> bool {
-        if !self.sink.empty() {
+        if self.pending_load.is_some() {
+            return self.finish_load();
+        }
+
+        if self.sink.empty() {
+            self.preloaded_song = None;
+
+            // The queue might have shrunk since the skip was requested
+            let next = match self.requested_position.take() {
+                Some(position) if position < self.queue.len() => Some(position),
+                Some(_) => None,
+                None if self.stopped => None,
+                None if self.stop_after_current && self.current_song.is_some() => {
+                    // The track the flag was set for just ended
+                    self.stop_after_current = false;
+                    self.stopped = true;
+                    None
+                }
+                None => self.next_position(),
+            };
+
+            let Some(next) = next else {
+                return false;
+            };
+
+            self.stopped = false;
+            self.start_load(next, LoadKind::Start);
+
             return false;
         }
 
-        self.player_index += 1;
+        // A skip is waiting for the sink to run empty
+        if self.requested_position.is_some() {
+            return false;
+        }
 
-        if self.player_index > self.queue.len() {
-            //self.player_index = 0;
-            self.player_index -= 1;
+        if self.preload_failed {
             return false;
         }
 
-        let song_ref = self.queue.get(self.player_index - 1).unwrap();
+        if self.preloaded_song.is_some() {
+            // The sink only drops the finished track once the preloaded one has taken over
+            if self.sink.len() == 1 {
+                let song = self.preloaded_song.take().unwrap();
+                self.switch_to(song);
+
+                return true;
+            }
+
+            return false;
+        }
 
-        self.current_song = Some(Song::new(&song_ref.file_path_clone()));
+        let Some(next) = self.next_position() else {
+            return false;
+        };
 
-        let file = File::open(song_ref.file_path.clone()).unwrap();
+        if let Some(crossfade) = self.crossfade {
+            if self.time_left().is_some_and(|left| left <= crossfade) {
+                self.start_crossfade(next, crossfade);
+            }
 
-        let buffered = BufReader::new(file);
-        let source: Decoder<BufReader<File>> = Decoder::try_from(buffered).unwrap();
-        self.current_song_duration = source.total_duration().clone();
-        self.sink.append(source);
+            return false;
+        }
 
-        let file = File::open(expand_tilde(
-            &self.current_song().unwrap().file_path_clone(),
-        ))
-        .unwrap();
-        self.decoder = Some(Decoder::new(BufReader::new(file)).unwrap().collect());
+        // Tracks of unknown length get their successor appended right away
+        if self.time_left().is_none_or(|left| left <= PRELOAD_WINDOW) {
+            self.start_load(next, LoadKind::Preload);
+        }
+
+        return false;
+    }
+
+    fn start_load(&mut self, position: usize, kind: LoadKind) -> () {
+        let song = self.song_at(position).unwrap().clone();
+
+        self.pending_load = Some(PendingLoad {
+            queue_index: self.play_order.get(position).unwrap(),
+            kind,
+            source: open_in_background(song),
+        });
+    }
+
+    // Appends the track that was being opened once it's ready, returns whether it took over
+    fn finish_load(&mut self) -> bool {
+        let load = self.pending_load.as_mut().unwrap();
+
+        // The current track ended before the next one was ready
+        if load.kind == LoadKind::Preload && self.sink.empty() {
+            load.kind = LoadKind::Start;
+        }
+
+        let Some(result) = poll(&load.source) else {
+            return false;
+        };
+        let load = self.pending_load.take().unwrap();
+        let position = self.play_order.position_of(load.queue_index).unwrap();
+
+        let source = match result {
+            Ok(source) => source,
+            Err(error) => {
+                match load.kind {
+                    LoadKind::Preload => self.preload_failed = true,
+                    _ => self.skip_unplayable(position, error),
+                }
+                return false;
+            }
+        };
+
+        match load.kind {
+            LoadKind::Start => {
+                let song = self.append_song(position, source, None);
+                self.switch_to(song);
+
+                if let Some(offset) = self.resume_offset.take()
+                    && let Err(error) = self.sink.try_seek(offset)
+                {
+                    // It plays from the start instead
+                    self.error = Some(format!(
+                        "Couldn't resume where playback was left: {}",
+                        error
+                    ));
+                }
+            }
+            LoadKind::Preload => {
+                self.preloaded_song = Some(self.append_song(position, source, None));
+                return false;
+            }
+            LoadKind::Crossfade(length) => {
+                let song = self.append_song(position, source, Some(length));
+                self.switch_to(song);
+            }
+        }
 
         return true;
     }
 
+    // Whether the track that is about to play is still being opened
+    pub fn loading(&self) -> bool {
+        return self
+            .pending_load
+            .as_ref()
+            .is_some_and(|load| load.kind != LoadKind::Preload);
+    }
+
+    // Position of the track that follows the current one when it ends by itself
+    fn next_position(&self) -> Option<usize> {
+        if self.stop_after_current {
+            return None;
+        }
+
+        match self.repeat_mode {
+            RepeatMode::One if self.player_index > 0 => return Some(self.player_index - 1),
+            RepeatMode::All if self.player_index >= self.queue.len() && !self.queue.is_empty() => {
+                return Some(0);
+            }
+            _ => {}
+        }
+
+        if self.player_index < self.queue.len() {
+            return Some(self.player_index);
+        }
+
+        return None;
+    }
+
+    // Drops the preloaded track. It is already in the sink, so it gets cut off as soon as it starts.
+    // A track that is still being opened to take over gets requested again instead,
+    // so that it starts once the sink is empty
+    fn discard_preloaded_song(&mut self) -> () {
+        self.preload_failed = false;
+        if let Some(song) = self.preloaded_song.take() {
+            song.fade_out.cut();
+        }
+
+        if let Some(load) = self.pending_load.take()
+            && load.kind != LoadKind::Preload
+            && self.requested_position.is_none()
+        {
+            self.requested_position = self.play_order.position_of(load.queue_index);
+        }
+    }
+
+    // Fades the current track out on the fading sink while the next one fades in on the main sink,
+    // as soon as it's opened. If the next track can't be opened the current one still fades out
+    fn start_crossfade(&mut self, next: usize, crossfade: Duration) -> () {
+        self.current_fade_out.start(self.sink.get_pos(), crossfade);
+
+        // Whatever was still fading out from an earlier crossfade gets cut off
+        self.fading_sink.stop();
+        std::mem::swap(&mut self.sink, &mut self.fading_sink);
+
+        if self.fading_sink.is_paused() {
+            self.sink.pause();
+        } else {
+            self.sink.play();
+        }
+
+        self.start_load(next, LoadKind::Crossfade(crossfade));
+    }
+
+    // Leaves a track that couldn't be opened behind as if it had been played
+    fn skip_unplayable(&mut self, position: usize, error: Report) -> () {
+        let song = self.song_at(position).unwrap();
+        self.error = Some(format!(
+            "Skipped {}: {}",
+            song.file_path_as_path().display(),
+            error
+        ));
+
+        self.player_index = position + 1;
+        self.current_song = None;
+        self.current_song_duration = None;
+        self.resume_offset = None;
+
+        // Repeating a broken track would only fail over and over
+        if self.repeat_mode == RepeatMode::One {
+            self.repeat_mode = RepeatMode::Off;
+        }
+    }
+
+    pub fn take_error(&mut self) -> Option<String> {
+        return self.error.take();
+    }
+
+    fn append_song(
+        &self,
+        position: usize,
+        source: AudioSource,
+        fade_in: Option<Duration>,
+    ) -> LoadedSong {
+        let song = self.song_at(position).unwrap().clone();
+
+        let duration = source.total_duration();
+        let fade_out = FadeOut::default();
+        let replay_gain = Arc::new(Mutex::new(self.replay_gain_factor(position)));
+        self.sink.append(Self::fading_source(
+            source,
+            fade_in,
+            fade_out.clone(),
+            replay_gain.clone(),
+        ));
+
+        return LoadedSong {
+            queue_index: self.play_order.get(position).unwrap(),
+            song,
+            duration,
+            fade_out,
+            replay_gain,
+        };
+    }
+
+    // Wraps a track so that its gain follows its own playback position, which keeps fades in
+    // step with pausing and seeking. The track ends by itself once it has been faded out.
+    // The ReplayGain factor is shared so that changing the mode affects the playing track too
+    fn fading_source<S: Source + Send + 'static>(
+        source: S,
+        fade_in: Option<Duration>,
+        fade_out: FadeOut,
+        replay_gain: Arc<Mutex<f32>>,
+    ) -> impl Source + Send + 'static {
+        source
+            .track_position()
+            .amplify(1.0)
+            .stoppable()
+            .periodic_access(FADE_UPDATE_PERIOD, move |src| {
+                let position = src.inner().inner().get_pos();
+
+                let fade_in_gain = match fade_in {
+                    Some(length) if position < length => {
+                        position.as_secs_f32() / length.as_secs_f32()
+                    }
+                    _ => 1.0,
+                };
+
+                let replay_gain = *replay_gain.lock().unwrap();
+
+                match fade_out.gain(position) {
+                    Some(gain) => src
+                        .inner_mut()
+                        .set_factor(replay_gain * fade_in_gain * gain),
+                    None => src.stop(),
+                }
+            })
+    }
+
+    fn switch_to(&mut self, song: LoadedSong) -> () {
+        self.preload_failed = false;
+        self.player_index = self.play_order.position_of(song.queue_index).unwrap() + 1;
+        self.current_song = Some(song.song);
+        self.current_song_duration = song.duration;
+        self.current_fade_out = song.fade_out;
+        self.current_replay_gain = song.replay_gain;
+    }
+
+    fn replay_gain_factor(&self, position: usize) -> f32 {
+        let song = self.song_at(position).unwrap();
+
+        match self.replay_gain_mode {
+            ReplayGainMode::Off => return 1.0,
+            ReplayGainMode::Track => return song.replay_gain_factor(false),
+            ReplayGainMode::Album => return song.replay_gain_factor(true),
+            ReplayGainMode::Auto => return song.replay_gain_factor(self.in_album_run(position)),
+        }
+    }
+
+    fn song_at(&self, position: usize) -> Option<&Song> {
+        return self
+            .play_order
+            .get(position)
+            .and_then(|queue_index| self.queue.get(queue_index));
+    }
+
+    // Whether a neighbouring track in the play order is from the same album,
+    // i.e. the album is played as a whole
+    fn in_album_run(&self, position: usize) -> bool {
+        let Some(album) = &self.song_at(position).unwrap().album else {
+            return false;
+        };
+
+        let previous = position
+            .checked_sub(1)
+            .and_then(|position| self.song_at(position));
+        let next = self.song_at(position + 1);
+
+        return previous
+            .into_iter()
+            .chain(next)
+            .any(|song| song.album.as_ref() == Some(album));
+    }
+
+    pub fn cycle_replay_gain_mode(&mut self) -> () {
+        self.replay_gain_mode = match self.replay_gain_mode {
+            ReplayGainMode::Off => ReplayGainMode::Track,
+            ReplayGainMode::Track => ReplayGainMode::Album,
+            ReplayGainMode::Album => ReplayGainMode::Auto,
+            ReplayGainMode::Auto => ReplayGainMode::Off,
+        };
+
+        // Tracks that are already in the sink pick up the new gain right away
+        if self.current_song.is_some() && self.player_index > 0 {
+            *self.current_replay_gain.lock().unwrap() =
+                self.replay_gain_factor(self.player_index - 1);
+        }
+        if let Some(preloaded) = &self.preloaded_song {
+            *preloaded.replay_gain.lock().unwrap() = self.replay_gain_factor(self.player_index);
+        }
+    }
+
+    pub fn replay_gain_mode(&self) -> ReplayGainMode {
+        return self.replay_gain_mode;
+    }
+
+    fn time_left(&self) -> Option<Duration> {
+        return self
+            .current_song_duration
+            .map(|duration| duration.saturating_sub(self.sink.get_pos()));
+    }
+
+    pub fn saved_state(&self) -> SavedState {
+        let mut state = SavedState::new();
+
+        state.queue = self
+            .queue
+            .iter()
+            .map(|song| SavedEntry {
+                path: song.file_path_clone(),
+                cue_track: song.cue_track.clone(),
+            })
+            .collect();
+        state.play_order = self.play_order.as_slice().to_vec();
+        state.original_order = self.play_order.original().to_vec();
+        state.shuffled = self.play_order.is_shuffled();
+
+        state.player_index = self.player_index;
+        if !self.sink.empty() {
+            state.position_secs = self.sink.get_pos().as_secs_f64();
+        }
+
+        state.volume = self.volume;
+        state.repeat_mode = self.repeat_mode;
+
+        return state;
+    }
+
+    // Loads the queue from a saved state and gets the saved track ready, paused at the saved position.
+    // Entries whose files are gone or can't be read anymore are left out
+    pub fn restore_state(&mut self, state: SavedState) -> () {
+        self.set_volume(state.volume);
+        self.repeat_mode = state.repeat_mode;
+
+        let len = state.queue.len();
+        if !self
+            .play_order
+            .restore(state.play_order, state.original_order, state.shuffled, len)
+        {
+            return;
+        }
+
+        let (songs, player_index, current_removed) =
+            restore_queue(state.queue, &mut self.play_order, state.player_index);
+
+        self.queue = songs;
+        self.queue_changes += 1;
+
+        // The saved track continues where it was left off,
+        // if it is gone the one after it starts from its beginning instead
+        let (start, offset) = match current_removed {
+            true => (player_index, Duration::ZERO),
+            false => (
+                player_index.saturating_sub(1),
+                Duration::from_secs_f64(state.position_secs.max(0.0)),
+            ),
+        };
+
+        if start < self.queue.len() {
+            self.sink.pause();
+            self.requested_position = Some(start);
+            self.resume_offset = Some(offset);
+        }
+    }
+
     pub fn get_metadata(&self, metadata_type: MetadataType) -> String {
         match self.sink.empty() {
             false => match &self.current_song {
@@ -102,6 +654,22 @@ impl Player {
                     MetadataType::Album => return curr_song.album_clone(),
                     MetadataType::Title => return curr_song.title_clone(),
                     MetadataType::TrackArtist => return curr_song.artist_clone(),
+                    MetadataType::AlbumArtist => return or_dash(&curr_song.album_artist),
+                    MetadataType::Composer => return or_dash(&curr_song.composer),
+                    MetadataType::Genre => return or_dash(&curr_song.genre),
+                    MetadataType::Date => return or_dash(&curr_song.date),
+                    MetadataType::Comment => return or_dash(&curr_song.comment),
+                    MetadataType::TrackNumber => {
+                        return position_of(curr_song.track_number, curr_song.track_total);
+                    }
+                    MetadataType::DiscNumber => {
+                        return position_of(curr_song.disc_number, curr_song.disc_total);
+                    }
+                    MetadataType::Duration => {
+                        let secs = curr_song.duration.as_secs();
+                        return format!("{}:{:02}", secs / 60, secs % 60);
+                    }
+                    MetadataType::Format => return curr_song.format_description(),
                 },
                 None => {
                     return "Nothing".to_string();
@@ -118,16 +686,42 @@ impl Player {
             return;
         }
 
-        self.sink.skip_one();
-    }
+        // Skipping moves on even when repeating the current track
+        let next = if self.player_index < self.queue.len() {
+            Some(self.player_index)
+        } else if self.repeat_mode == RepeatMode::All && !self.queue.is_empty() {
+            Some(0)
+        } else {
+            None
+        };
 
-    pub fn clear_queue(&mut self) -> () {
-        if self.sink.empty() {
+        self.discard_preloaded_song();
+
+        // Skipping fades into the next track like a regular track change would
+        if let Some(crossfade) = self.crossfade
+            && let Some(next) = next
+        {
+            self.start_crossfade(next, crossfade);
             return;
         }
 
+        match next {
+            Some(next) => self.requested_position = Some(next),
+            None => self.stopped = true,
+        }
+        self.sink.stop();
+    }
+
+    // Empties the queue, the current track keeps playing until it ends
+    pub fn clear_queue(&mut self) -> () {
+        self.discard_preloaded_song();
+
         self.queue.clear();
+        self.queue_changes += 1;
+        self.missing_files.clear();
+        self.play_order.clear();
         self.player_index = 0;
+        self.requested_position = None;
     }
 
     pub fn return_last_song(&mut self) -> () {
@@ -135,26 +729,180 @@ impl Player {
             return;
         }
 
-        match self.player_index {
-            0 => {}
-            1 => self.player_index = 0,
-            _ => self.player_index -= 2,
+        let previous = if self.player_index >= 2 {
+            self.player_index - 2
+        } else if self.repeat_mode == RepeatMode::All {
+            self.queue.len().saturating_sub(1)
+        } else {
+            0
+        };
+
+        self.discard_preloaded_song();
+        self.requested_position = Some(previous);
+        self.sink.stop();
+
+        // Going back is a hard cut, so a track that is still fading out stops right away
+        self.fading_sink.stop();
+    }
+
+    pub fn toggle_shuffle(&mut self) -> () {
+        let player_index = self.player_index;
+
+        self.reorder(|play_order| match play_order.is_shuffled() {
+            true => play_order.unshuffle(),
+            false => play_order.shuffle(player_index),
+        });
+    }
+
+    // Changes the play order while keeping the current and the requested position
+    // on the queue entries they pointed at before
+    fn reorder(&mut self, edit: impl FnOnce(&mut PlayOrder)) -> () {
+        // The preloaded track was picked from the old order
+        self.discard_preloaded_song();
+
+        let current = self
+            .player_index
+            .checked_sub(1)
+            .and_then(|position| self.play_order.get(position));
+        let requested = self
+            .requested_position
+            .and_then(|position| self.play_order.get(position));
+
+        edit(&mut self.play_order);
+
+        if let Some(current) = current {
+            self.player_index = self.play_order.position_of(current).unwrap() + 1;
+        }
+        self.requested_position =
+            requested.and_then(|queue_index| self.play_order.position_of(queue_index));
+    }
+
+    pub fn move_in_queue(&mut self, from: usize, to: usize) -> () {
+        if from >= self.queue.len() || to >= self.queue.len() {
+            return;
+        }
+
+        self.reorder(|play_order| play_order.move_entry(from, to));
+    }
+
+    // Moves the track at `position` right behind the current one
+    pub fn play_next(&mut self, position: usize) -> () {
+        let to = match position < self.player_index {
+            true => self.player_index - 1,
+            false => self.player_index,
+        };
+
+        self.move_in_queue(position, to);
+    }
+
+    // Starts the track at `position` right away, fading into it if crossfading is enabled
+    pub fn play_now(&mut self, position: usize) -> () {
+        if position >= self.queue.len() {
+            return;
+        }
+
+        self.discard_preloaded_song();
+        self.stopped = false;
+
+        if let Some(crossfade) = self.crossfade
+            && !self.sink.empty()
+        {
+            self.start_crossfade(position, crossfade);
+            return;
         }
 
-        self.sink.skip_one();
+        self.requested_position = Some(position);
+        self.sink.stop();
+    }
+
+    pub fn remove_from_queue(&mut self, position: usize) -> () {
+        if position >= self.queue.len() {
+            return;
+        }
+
+        self.discard_preloaded_song();
+
+        let queue_index = self.play_order.remove(position);
+        self.queue.remove(queue_index);
+        self.queue_changes += 1;
+
+        if let Some(requested) = self.requested_position
+            && requested > position
+        {
+            self.requested_position = Some(requested - 1);
+        }
+
+        let current = self.player_index.checked_sub(1);
+        if current.is_some_and(|current| position < current) {
+            self.player_index -= 1;
+        } else if current == Some(position) {
+            // Whatever came after the removed track takes its place
+            self.player_index -= 1;
+
+            if !self.sink.empty() {
+                self.requested_position = Some(position);
+                self.sink.stop();
+            }
+        }
+    }
+
+    pub fn shuffled(&self) -> bool {
+        return self.play_order.is_shuffled();
+    }
+
+    pub fn cycle_repeat_mode(&mut self) -> () {
+        self.repeat_mode = match self.repeat_mode {
+            RepeatMode::Off => RepeatMode::All,
+            RepeatMode::All => RepeatMode::One,
+            RepeatMode::One => RepeatMode::Off,
+        };
+
+        // The preloaded track was picked under the old mode
+        self.discard_preloaded_song();
+    }
+
+    pub fn repeat_mode(&self) -> RepeatMode {
+        return self.repeat_mode;
+    }
+
+    pub fn toggle_stop_after_current(&mut self) -> () {
+        self.stop_after_current = !self.stop_after_current;
+
+        self.discard_preloaded_song();
+    }
+
+    pub fn stop_after_current(&self) -> bool {
+        return self.stop_after_current;
+    }
+
+    pub fn stopped(&self) -> bool {
+        return self.stopped;
+    }
+
+    pub fn cycle_crossfade(&mut self) -> () {
+        let step = CROSSFADE_STEPS
+            .iter()
+            .position(|step| *step == self.crossfade)
+            .unwrap_or(0);
+
+        self.crossfade = CROSSFADE_STEPS[(step + 1) % CROSSFADE_STEPS.len()];
+    }
+
+    pub fn crossfade(&self) -> Option<Duration> {
+        return self.crossfade;
     }
 
     fn set_volume(&mut self, volume: f32) -> () {
         if volume > 1.0 {
             self.volume = 1.0;
-            self.sink.set_volume(1.0);
         } else if volume < 0.0 {
             self.volume = 0.0;
-            self.sink.set_volume(0.0);
         } else {
             self.volume = volume;
-            self.sink.set_volume(volume);
         }
+
+        self.sink.set_volume(self.volume);
+        self.fading_sink.set_volume(self.volume);
     }
 
     pub fn change_volume(&mut self, amount: f32) -> () {
@@ -165,13 +913,21 @@ impl Player {
         return self.volume;
     }
 
-    pub fn play_pause(&self) -> () {
+    pub fn play_pause(&mut self) -> () {
+        // Resuming after a stop starts the next track instead of unpausing the empty sink
+        if self.stopped {
+            self.stopped = false;
+            return;
+        }
+
         match self.sink.is_paused() {
             true => {
                 self.sink.play();
+                self.fading_sink.play();
             }
             false => {
                 self.sink.pause();
+                self.fading_sink.pause();
             }
         }
     }
@@ -184,132 +940,130 @@ impl Player {
         return &self.queue;
     }
 
-    pub fn current_song(&self) -> Option<&Song> {
-        match &self.current_song {
-            Some(song) => return Some(song),
-            None => return None,
+    // Looks again at the files of the queue entries at or below the changed paths,
+    // e.g. after the file watcher saw them being removed or created
+    pub fn check_missing_files(&mut self, changed_paths: &[PathBuf]) -> () {
+        for song in &self.queue {
+            let path = song.file_path_as_path();
+            if !changed_paths
+                .iter()
+                .any(|changed| path.starts_with(changed))
+            {
+                continue;
+            }
+
+            match path.exists() {
+                true => self.missing_files.remove(&song.file_path),
+                false => self.missing_files.insert(song.file_path_clone()),
+            };
         }
     }
 
-    pub fn eq_bands(&mut self, n_bands: i32) -> Option<Vec<f32>> {
-        if self.sink.empty() {
-            return None;
-        }
+    pub fn file_missing(&self, song: &Song) -> bool {
+        return self.missing_files.contains(&song.file_path);
+    }
 
-        let song_ref = self.current_song.as_ref().unwrap();
+    pub fn queue_changes(&self) -> u64 {
+        return self.queue_changes;
+    }
 
-        let start: usize =
-            (self.sink.get_pos().as_millis() as usize) * song_ref.samplerate * song_ref.channels
-                / 1000;
+    pub fn play_order(&self) -> &PlayOrder {
+        return &self.play_order;
+    }
 
-        let decoder = self.decoder.clone().unwrap();
-        let left_channel: Vec<f32> = decoder
-            .iter()
-            .skip(start)
-            .take(EQ_BUFFER_SIZE * 2)
-            .enumerate()
-            .filter_map(|(i, val)| {
-                if i % song_ref.channels == 0 {
-                    Some(val)
-                } else {
-                    None
-                }
-            })
-            .map(|value| return *value)
-            .collect();
-        /*
-        let left_channel : Vec<f32> = waveform
+    // The whole queue, in the order it is going to be played
+    pub fn songs_in_play_order(&self) -> Vec<&Song> {
+        return self
+            .play_order
+            .as_slice()
             .iter()
-            .enumerate()
-            .filter_map(|(i, &val)| if i % 2 == 0 { Some(val) } else { None })
+            .map(|queue_index| self.queue.get(*queue_index).unwrap())
             .collect();
-        */
-        let mut buffer: Vec<Complex<f32>> = left_channel
-            .iter()
-            .map(|&re| Complex { re, im: 0.0 })
-            .collect();
-
-        let fft = self.fft_planner.plan_fft_forward(buffer.len());
+    }
 
-        fft.process(&mut buffer);
+    pub fn current_song(&self) -> Option<&Song> {
+        match &self.current_song {
+            Some(song) => return Some(song),
+            None => return None,
+        }
+    }
 
-        let magnitudes: Vec<f32> = buffer.iter().map(|c| c.norm()).collect();
+    // The latest played samples, interleaved like the output. `None` while nothing plays
+    pub fn latest_samples(&self) -> Option<Vec<f32>> {
+        if self.sink.empty() && self.fading_sink.empty() {
+            return None;
+        }
 
-        //println!("Magnitudes: {}", magnitudes.len());
+        let samples = self
+            .played_samples
+            .latest(EQ_BUFFER_SIZE * self.output_channels() as usize);
 
-        /*
-            let samples = decoder  // unwrap the Result, discard errors
-                .map(|sample| Sample::to_f32(&sample))
-                .take(FFT_SIZE)
-                .collect();
+        match samples.is_empty() {
+            true => return None,
+            false => return Some(samples),
+        }
+    }
 
-        */
-        return Some(
-            Self::split_into_bands(
-                &magnitudes,
-                song_ref.samplerate as f32,
-                EQ_BUFFER_SIZE,
-                n_bands as usize,
-            )
-            .unwrap(),
-        );
+    pub fn output_channels(&self) -> u16 {
+        return self.played_samples.channels();
     }
 
-    fn split_into_bands(
-        magnitudes: &[f32],
-        sample_rate: f32,
-        fft_size: usize,
-        n_bands: usize,
-    ) -> Option<Vec<f32>> {
-        let nyquist = sample_rate / 2.0;
+    // The spectrum of the latest played samples, `None` while nothing plays
+    pub fn eq_bands(&mut self, n_bands: i32) -> Option<SpectrumFrame> {
+        let samples = self.latest_samples()?;
 
-        let freq_per_bin = nyquist / magnitudes.len() as f32;
+        return Some(self.spectrum.analyse(
+            &samples,
+            self.output_channels(),
+            self.played_samples.sample_rate(),
+            n_bands.max(0) as usize,
+        ));
+    }
 
-        let min_freq: f32 = 20.0;
-        let max_freq: f32 = nyquist;
+    pub fn spectrum(&mut self) -> &mut Spectrum {
+        return &mut self.spectrum;
+    }
 
-        let log_min = min_freq.ln();
-        let log_max = max_freq.ln();
-        let band_edges: Vec<f32> = (0..=n_bands)
-            .map(|i| {
-                let t = i as f32 / n_bands as f32;
-                (log_min + t * (log_max - log_min)).exp()
-            })
-            .collect();
+    pub fn seek(&mut self, offset: f32) -> () {
+        let position = self.sink.get_pos().as_secs_f32() + offset;
 
-        let mut bands = vec![0.0; n_bands];
-        let mut counts = vec![0usize; n_bands];
+        self.seek_to(Duration::from_secs_f32(position.max(0.0)));
+    }
 
-        for (i, &mag) in magnitudes.iter().enumerate() {
-            let freq = i as f32 * freq_per_bin;
-            if freq < min_freq || freq > max_freq {
-                continue;
-            }
+    pub fn seek_to_fraction(&mut self, fraction: f32) -> () {
+        if let Some(duration) = self.current_song_duration {
+            self.seek_to(duration.mul_f32(fraction.clamp(0.0, 1.0)));
+        }
+    }
 
-            if let Some(band) = band_edges
-                .windows(2)
-                .position(|w| freq >= w[0] && freq < w[1])
-            {
-                bands[band] += mag;
-                counts[band] += 1;
-            }
+    fn seek_to(&mut self, position: Duration) -> () {
+        if self.sink.empty() {
+            return;
         }
 
-        for (b, &count) in bands.iter_mut().zip(&counts) {
-            if count > 0 {
-                *b /= count as f32;
-            }
+        // Seeking right onto the end makes some decoders fail, stop just short of it instead
+        let position = match self.current_song_duration {
+            Some(duration) => position.min(duration.saturating_sub(Duration::from_millis(500))),
+            None => position,
+        };
+
+        if let Err(error) = self.sink.try_seek(position) {
+            self.error = Some(format!("Couldn't seek: {}", error));
         }
+    }
 
-        if let Some(&max_val) = bands.iter().max_by(|a, b| a.partial_cmp(b).unwrap()) {
-            if max_val > 0.0 {
-                for b in &mut bands {
-                    *b /= max_val;
-                }
+    pub fn progress(&self) -> f64 {
+        match self.current_song_duration {
+            Some(duration) if !duration.is_zero() => {
+                (self.sink.get_pos().as_secs_f64() / duration.as_secs_f64()).clamp(0.0, 1.0)
             }
+            _ => 0.0,
         }
+    }
 
-        Some(bands)
+    // How far into the current song playback is
+    pub fn position(&self) -> Duration {
+        return self.sink.get_pos();
     }
 
     pub fn playback_time(&self) -> (usize, usize) {
@@ -341,10 +1095,62 @@ pub enum PlayerCommand {
     Prev,
     PlayPause,
     VolumeChange(f32),
+    Seek(f32),
+    SeekTo(f32),
+    CycleCrossfade,
+    CycleReplayGain,
+    CycleRepeat,
+    ToggleStopAfterCurrent,
+    ToggleShuffle,
+    MoveInQueue(usize, usize),
+    PlayNext(usize),
+    PlayNow(usize),
+    RemoveFromQueue(usize),
+    ClearQueue,
+    CycleSpectrumFloor,
+    CycleSpectrumWindow,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum RepeatMode {
+    Off,
+    One,
+    All,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ReplayGainMode {
+    Off,
+    Track,
+    Album,
+    // Album gain while a neighbouring queue entry is from the same album, track gain otherwise
+    Auto,
 }
 
 pub enum MetadataType {
     Title,
     TrackArtist,
     Album,
+    AlbumArtist,
+    Composer,
+    Genre,
+    Date,
+    Comment,
+    TrackNumber,
+    DiscNumber,
+    Duration,
+    Format,
+}
+
+fn or_dash(value: &Option<String>) -> String {
+    return value.clone().unwrap_or(String::from("-"));
+}
+
+// Track and disc numbers, like "3/12" when the total is tagged as well
+fn position_of(number: Option<u32>, total: Option<u32>) -> String {
+    match (number, total) {
+        (Some(number), Some(total)) => return format!("{}/{}", number, total),
+        (Some(number), None) => return number.to_string(),
+        (None, _) => return String::from("-"),
+    }
 }
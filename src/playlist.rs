@@ -0,0 +1,845 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use quick_xml::Reader;
+use quick_xml::escape::escape;
+use quick_xml::events::Event;
+
+use lofty::file::FileType;
+
+use crate::cue::{CueTrack, embedded_cue_sheet, is_cue_sheet, parse_cue_sheet};
+use crate::formats::detect_format;
+use crate::song::Song;
+
+// A file to add to the queue, with the title a playlist gave it, if any
+pub struct PlaylistEntry {
+    pub path: PathBuf,
+    pub title: Option<String>,
+    // Only this part of the file gets played
+    pub cue_track: Option<CueTrack>,
+}
+
+// The entries of a playlist, along with the locations that didn't point to a playable file
+// and why they didn't
+pub struct Playlist {
+    pub entries: Vec<PlaylistEntry>,
+    pub unresolved: Vec<String>,
+}
+
+impl Playlist {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            unresolved: Vec::new(),
+        }
+    }
+
+    // The files of a directory. A CUE sheet takes the place of the files it splits into tracks,
+    // which would otherwise end up in the queue twice. Covers, logs and whatever else doesn't
+    // look like music are left out quietly unless their header says otherwise
+    pub fn from_paths(paths: Vec<PathBuf>) -> Self {
+        let mut sheets: HashMap<PathBuf, io::Result<Playlist>> = paths
+            .iter()
+            .filter(|path| is_cue_sheet(path))
+            .map(|path| (path.clone(), read_playlist(path)))
+            .collect();
+
+        let described: Vec<PathBuf> = sheets
+            .values()
+            .flatten()
+            .flat_map(|sheet| sheet.entries.iter().map(|entry| entry.path.clone()))
+            .collect();
+
+        let mut playlist = Self::new();
+        for path in paths {
+            match sheets.remove(&path) {
+                Some(Ok(sheet)) => playlist.append(sheet),
+                Some(Err(error)) => {
+                    playlist
+                        .unresolved
+                        .push(format!("{} ({})", path.display(), error))
+                }
+                None if !described.contains(&path) => {
+                    let report_failure = looks_like_music(&path);
+                    playlist.push_file(path, None, report_failure);
+                }
+                None => {}
+            }
+        }
+
+        return playlist;
+    }
+
+    fn append(&mut self, mut other: Playlist) -> () {
+        self.entries.append(&mut other.entries);
+        self.unresolved.append(&mut other.unresolved);
+    }
+
+    // A file that was picked on its own, which gets a reason if it can't be played
+    pub fn from_file(path: PathBuf) -> Self {
+        if is_cue_sheet(&path) {
+            return Self::from_paths(vec![path]);
+        }
+
+        let mut playlist = Self::new();
+        playlist.push_file(path, None, true);
+
+        return playlist;
+    }
+
+    // Adds the file if it can be played, otherwise notes down why it was skipped if asked to.
+    // FLAC files with a CUE sheet in their tags are added as the tracks it lists
+    fn push_file(&mut self, path: PathBuf, title: Option<String>, report_failure: bool) -> () {
+        match detect_format(&path) {
+            Ok(file_type) => {
+                if file_type == FileType::Flac
+                    && let Some(sheet) = embedded_cue_sheet(&path)
+                {
+                    let tracks = parse_cue_sheet(&sheet);
+                    if !tracks.is_empty() {
+                        for entry in tracks {
+                            self.push_cue_track(path.clone(), entry.track);
+                        }
+                        return;
+                    }
+                }
+
+                self.entries.push(PlaylistEntry {
+                    path,
+                    title,
+                    cue_track: None,
+                });
+            }
+            Err(error) if report_failure => {
+                self.unresolved
+                    .push(format!("{} ({})", path.display(), error))
+            }
+            Err(_) => {}
+        }
+    }
+
+    fn push_cue_track(&mut self, path: PathBuf, track: CueTrack) -> () {
+        self.entries.push(PlaylistEntry {
+            path,
+            title: None,
+            cue_track: Some(track),
+        });
+    }
+
+    fn push(&mut self, location: &str, title: Option<String>, base_dir: &Path, is_uri: bool) -> () {
+        match resolve_location(location, base_dir, is_uri) {
+            Some(path) => self.push_file(path, title, true),
+            None => self
+                .unresolved
+                .push(format!("{} (not a local file)", location)),
+        }
+    }
+}
+
+// Whether the extension is one that music files have, going by lofty's list
+fn looks_like_music(path: &Path) -> bool {
+    return path
+        .extension()
+        .is_some_and(|ext| FileType::from_ext(ext).is_some());
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PlaylistFormat {
+    M3u,
+    Pls,
+    Xspf,
+}
+
+impl PlaylistFormat {
+    pub fn of(path: &Path) -> Option<Self> {
+        match extension_of(path).as_deref() {
+            Some("m3u" | "m3u8") => return Some(Self::M3u),
+            Some("pls") => return Some(Self::Pls),
+            Some("xspf") => return Some(Self::Xspf),
+            _ => return None,
+        }
+    }
+
+    // The extension exported playlists get
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::M3u => return "m3u8",
+            Self::Pls => return "pls",
+            Self::Xspf => return "xspf",
+        }
+    }
+}
+
+pub fn is_playlist(path: &Path) -> bool {
+    return PlaylistFormat::of(path).is_some() || is_cue_sheet(path);
+}
+
+fn extension_of(path: &Path) -> Option<String> {
+    return path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase());
+}
+
+pub fn read_playlist(path: &Path) -> io::Result<Playlist> {
+    // Plain .m3u files aren't necessarily UTF-8, so anything else is read lossily
+    let bytes = fs::read(path)?;
+    let contents = String::from_utf8_lossy(&bytes);
+    let contents = contents.trim_start_matches('\u{feff}');
+
+    let base_dir = path.parent().unwrap_or(Path::new("/"));
+
+    if is_cue_sheet(path) {
+        return Ok(parse_cue(contents, base_dir));
+    }
+
+    match PlaylistFormat::of(path) {
+        Some(PlaylistFormat::Pls) => return Ok(parse_pls(contents, base_dir)),
+        Some(PlaylistFormat::Xspf) => return parse_xspf(contents, base_dir),
+        _ => return Ok(parse_m3u(contents, base_dir)),
+    }
+}
+
+fn parse_m3u(contents: &str, base_dir: &Path) -> Playlist {
+    let mut playlist = Playlist::new();
+    let mut title: Option<String> = None;
+
+    for line in contents.lines() {
+        let line = line.trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        // #EXTINF:<seconds>,<display title> describes the entry on the next line
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            title = info
+                .split_once(',')
+                .map(|(_, title)| title.trim().to_string())
+                .filter(|title| !title.is_empty());
+            continue;
+        }
+
+        if line.starts_with('#') {
+            continue;
+        }
+
+        playlist.push(line, title.take(), base_dir, false);
+    }
+
+    return playlist;
+}
+
+// PLS is INI-style, entries are numbered FileN=/TitleN= keys that may come in any order
+fn parse_pls(contents: &str, base_dir: &Path) -> Playlist {
+    let mut files: BTreeMap<u32, String> = BTreeMap::new();
+    let mut titles: BTreeMap<u32, String> = BTreeMap::new();
+
+    for line in contents.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_lowercase();
+        let value = value.trim().to_string();
+
+        if let Some(n) = key.strip_prefix("file").and_then(|n| n.parse().ok()) {
+            files.insert(n, value);
+        } else if let Some(n) = key.strip_prefix("title").and_then(|n| n.parse().ok())
+            && !value.is_empty()
+        {
+            titles.insert(n, value);
+        }
+    }
+
+    let mut playlist = Playlist::new();
+    for (n, location) in files {
+        playlist.push(&location, titles.remove(&n), base_dir, false);
+    }
+
+    return playlist;
+}
+
+fn parse_cue(contents: &str, base_dir: &Path) -> Playlist {
+    let mut playlist = Playlist::new();
+
+    // Whether each file the sheet names can be played, so that it's only checked once
+    let mut files: HashMap<String, Result<PathBuf, String>> = HashMap::new();
+
+    for entry in parse_cue_sheet(contents) {
+        let file = files.entry(entry.file.clone()).or_insert_with(|| {
+            let path = resolve_cue_file(&entry.file, base_dir)
+                .ok_or_else(|| format!("{} (not found)", entry.file))?;
+
+            match detect_format(&path) {
+                Ok(_) => return Ok(path),
+                Err(error) => return Err(format!("{} ({})", path.display(), error)),
+            }
+        });
+
+        match file {
+            Ok(path) => playlist.push_cue_track(path.clone(), entry.track),
+            Err(error) => playlist.unresolved.push(error.clone()),
+        }
+    }
+
+    return playlist;
+}
+
+// Sheets made while ripping often still name the .wav after it was converted to FLAC,
+// so a file with the same name but another extension will do as well
+fn resolve_cue_file(file: &str, base_dir: &Path) -> Option<PathBuf> {
+    let path = base_dir.join(file);
+    if path.is_file() {
+        return Some(path);
+    }
+
+    let stem = path.file_stem()?;
+    return fs::read_dir(path.parent()?)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|candidate| {
+            candidate.file_stem() == Some(stem)
+                && !is_cue_sheet(candidate)
+                && detect_format(candidate).is_ok()
+        });
+}
+
+#[derive(Clone, Copy)]
+enum XspfField {
+    Location,
+    Title,
+    Creator,
+}
+
+// XSPF is XML, with a <track> per entry whose <location> is a URI
+fn parse_xspf(contents: &str, base_dir: &Path) -> io::Result<Playlist> {
+    let invalid = |error: quick_xml::Error| io::Error::new(io::ErrorKind::InvalidData, error);
+
+    let mut reader = Reader::from_str(contents);
+    reader.config_mut().trim_text(true);
+
+    let mut playlist = Playlist::new();
+
+    let mut in_track = false;
+    let mut field: Option<XspfField> = None;
+    let mut location: Option<String> = None;
+    let mut title: Option<String> = None;
+    let mut creator: Option<String> = None;
+
+    loop {
+        match reader.read_event().map_err(invalid)? {
+            Event::Start(tag) => match tag.local_name().as_ref() {
+                b"track" => {
+                    in_track = true;
+                    location = None;
+                    title = None;
+                    creator = None;
+                }
+                // Only the first location of a track is used, the others are alternatives
+                b"location" if in_track && location.is_none() => field = Some(XspfField::Location),
+                b"title" if in_track => field = Some(XspfField::Title),
+                b"creator" if in_track => field = Some(XspfField::Creator),
+                _ => field = None,
+            },
+            Event::Text(text) => {
+                let text = text.unescape().map_err(invalid)?.to_string();
+                match field {
+                    Some(XspfField::Location) => location = Some(text),
+                    Some(XspfField::Title) => title = Some(text),
+                    Some(XspfField::Creator) => creator = Some(text),
+                    None => {}
+                }
+            }
+            Event::End(tag) => {
+                field = None;
+
+                if tag.local_name().as_ref() == b"track" {
+                    in_track = false;
+
+                    // The title is given the same "artist - title" shape M3U titles have
+                    let display_title = match (creator.take(), title.take()) {
+                        (Some(creator), Some(title)) => Some(format!("{} - {}", creator, title)),
+                        (None, title) => title,
+                        (Some(_), None) => None,
+                    };
+
+                    if let Some(location) = location.take() {
+                        playlist.push(&location, display_title, base_dir, true);
+                    }
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    return Ok(playlist);
+}
+
+// Turns a playlist location into a path, relative ones being relative to the playlist itself.
+// Streams and other remote locations can't be played and give `None`
+fn resolve_location(location: &str, base_dir: &Path, is_uri: bool) -> Option<PathBuf> {
+    let path = if let Some(uri_path) = location.strip_prefix("file://") {
+        // file://localhost/music/a.flac means the same as file:///music/a.flac
+        let uri_path = uri_path.strip_prefix("localhost").unwrap_or(uri_path);
+        PathBuf::from(percent_decode(uri_path))
+    } else if location.contains("://") {
+        return None;
+    } else if is_uri {
+        PathBuf::from(percent_decode(location))
+    } else {
+        PathBuf::from(location)
+    };
+
+    match path.is_absolute() {
+        true => return Some(path),
+        false => return Some(base_dir.join(path)),
+    }
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(byte) = text
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            decoded.push(byte);
+            i += 3;
+            continue;
+        }
+
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+
+    return String::from_utf8_lossy(&decoded).to_string();
+}
+
+// Characters that can stay as they are in the path of a file:// URI
+fn is_uri_safe(byte: u8) -> bool {
+    return byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte);
+}
+
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+
+    for byte in text.bytes() {
+        match is_uri_safe(byte) {
+            true => encoded.push(byte as char),
+            false => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    return encoded;
+}
+
+// Tracks of a CUE sheet are left out, as the playlist could only point at their whole file.
+// Gives how many were left out
+pub fn write_playlist(path: &Path, format: PlaylistFormat, songs: &[&Song]) -> io::Result<usize> {
+    let files: Vec<&Song> = songs
+        .iter()
+        .copied()
+        .filter(|song| song.cue_track.is_none())
+        .collect();
+
+    let contents = match format {
+        PlaylistFormat::M3u => m3u_contents(&files),
+        PlaylistFormat::Pls => pls_contents(&files),
+        PlaylistFormat::Xspf => xspf_contents(&files),
+    };
+    fs::write(path, contents)?;
+
+    return Ok(songs.len() - files.len());
+}
+
+// An extended M3U playlist with absolute paths
+fn m3u_contents(songs: &[&Song]) -> String {
+    let mut contents = String::from("#EXTM3U\n");
+
+    for song in songs {
+        contents.push_str(&format!(
+            "#EXTINF:{},{}\n{}\n",
+            song.duration.as_secs(),
+            display_title(song),
+            song.file_path
+        ));
+    }
+
+    return contents;
+}
+
+fn pls_contents(songs: &[&Song]) -> String {
+    let mut contents = String::from("[playlist]\n");
+
+    for (n, song) in songs.iter().enumerate() {
+        let n = n + 1;
+        contents.push_str(&format!(
+            "File{n}={}\nTitle{n}={}\nLength{n}={}\n",
+            song.file_path,
+            display_title(song),
+            song.duration.as_secs()
+        ));
+    }
+
+    contents.push_str(&format!("NumberOfEntries={}\nVersion=2\n", songs.len()));
+
+    return contents;
+}
+
+fn xspf_contents(songs: &[&Song]) -> String {
+    let mut contents = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n  <trackList>\n",
+    );
+
+    for song in songs {
+        contents.push_str("    <track>\n");
+        contents.push_str(&format!(
+            "      <location>file://{}</location>\n",
+            escape(percent_encode(&song.file_path))
+        ));
+
+        // Untagged fields are left out
+        if let Some(title) = &song.title {
+            contents.push_str(&format!("      <title>{}</title>\n", escape(title)));
+        }
+        if let Some(artist) = &song.artist {
+            contents.push_str(&format!("      <creator>{}</creator>\n", escape(artist)));
+        }
+        if let Some(album) = &song.album {
+            contents.push_str(&format!("      <album>{}</album>\n", escape(album)));
+        }
+        if let Some(number) = song.track_number {
+            contents.push_str(&format!("      <trackNum>{}</trackNum>\n", number));
+        }
+        contents.push_str(&format!(
+            "      <duration>{}</duration>\n",
+            song.duration.as_millis()
+        ));
+
+        contents.push_str("    </track>\n");
+    }
+
+    contents.push_str("  </trackList>\n</playlist>\n");
+
+    return contents;
+}
+
+fn display_title(song: &Song) -> String {
+    match (&song.artist, &song.title) {
+        (Some(artist), Some(title)) => return format!("{} - {}", artist, title),
+        _ => return song.title_clone(),
+    }
+}
+
+// A file name in `dir` that isn't taken yet, like `gigr-queue.m3u8` or `gigr-queue-2.m3u8`
+pub fn unused_file_name(dir: &Path, stem: &str, extension: &str) -> PathBuf {
+    let mut path = dir.join(format!("{}.{}", stem, extension));
+    let mut n = 2;
+
+    while path.exists() {
+        path = dir.join(format!("{}-{}.{}", stem, n, extension));
+        n += 1;
+    }
+
+    return path;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use crate::testing::{test_dir, write_wav};
+
+    // Path and title of every entry
+    fn entries(playlist: &Playlist) -> Vec<(PathBuf, Option<String>)> {
+        return playlist
+            .entries
+            .iter()
+            .map(|entry| (entry.path.clone(), entry.title.clone()))
+            .collect();
+    }
+
+    #[test]
+    fn m3u_titles_go_with_the_next_entry() {
+        let dir = test_dir("playlist-m3u");
+        write_wav(&dir.join("a.wav"));
+        write_wav(&dir.join("sub/b.wav"));
+        write_wav(&dir.join("c.wav"));
+
+        let contents = format!(
+            "#EXTM3U
+#EXTINF:1,Miles Davis - So What
+a.wav
+
+# A comment, not a file
+sub/b.wav
+#EXTINF:1,
+{}
+#EXTINF:300,Radio
+http://example.com/stream
+",
+            dir.join("c.wav").display()
+        );
+        let playlist = parse_m3u(&contents, &dir);
+
+        assert_eq!(
+            entries(&playlist),
+            vec![
+                (dir.join("a.wav"), Some("Miles Davis - So What".to_string())),
+                (dir.join("sub/b.wav"), None),
+                (dir.join("c.wav"), None),
+            ]
+        );
+        assert_eq!(
+            playlist.unresolved,
+            vec!["http://example.com/stream (not a local file)"]
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn locations_resolve_against_the_playlist_directory() {
+        let base_dir = Path::new("/music/playlists");
+        let resolve = |location, is_uri| resolve_location(location, base_dir, is_uri);
+
+        assert_eq!(
+            resolve("a b.flac", false),
+            Some(PathBuf::from("/music/playlists/a b.flac"))
+        );
+        assert_eq!(
+            resolve("../jazz/a.flac", false),
+            Some(PathBuf::from("/music/playlists/../jazz/a.flac"))
+        );
+        assert_eq!(
+            resolve("/music/a.flac", false),
+            Some(PathBuf::from("/music/a.flac"))
+        );
+        // Plain paths are taken as they are, URIs are decoded
+        assert_eq!(
+            resolve("100%25.flac", false),
+            Some(PathBuf::from("/music/playlists/100%25.flac"))
+        );
+        assert_eq!(
+            resolve("a%20b.flac", true),
+            Some(PathBuf::from("/music/playlists/a b.flac"))
+        );
+
+        for is_uri in [false, true] {
+            assert_eq!(
+                resolve("file:///music/a%20b.flac", is_uri),
+                Some(PathBuf::from("/music/a b.flac"))
+            );
+            assert_eq!(
+                resolve("file://localhost/music/a.flac", is_uri),
+                Some(PathBuf::from("/music/a.flac"))
+            );
+            assert_eq!(resolve("http://example.com/a.flac", is_uri), None);
+            assert_eq!(resolve("smb://server/music/a.flac", is_uri), None);
+        }
+    }
+
+    #[test]
+    fn percent_decoding() {
+        assert_eq!(percent_decode("a%20b%2Fc"), "a b/c");
+        assert_eq!(percent_decode("%c3%a9t%C3%A9"), "été");
+        // Anything that isn't an escape is kept
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%4"), "%zz%4");
+        assert_eq!(percent_decode("été"), "été");
+    }
+
+    // A tagged song, one with only a title and an untagged one, in files whose names need
+    // escaping in a URI
+    fn exported_songs(dir: &Path) -> Vec<Song> {
+        let paths = [
+            dir.join("So What & <More> #1.wav"),
+            dir.join("sub dir/été 100%.wav"),
+            dir.join("untagged.wav"),
+        ];
+
+        let mut songs: Vec<Song> = paths
+            .iter()
+            .map(|path| {
+                write_wav(path);
+                return Song::new(&path.to_string_lossy()).unwrap();
+            })
+            .collect();
+
+        songs[0].artist = Some("Miles Davis".to_string());
+        songs[0].title = Some("So What & <More>".to_string());
+        songs[1].title = Some("Été".to_string());
+
+        return songs;
+    }
+
+    #[test]
+    fn pls_round_trip() {
+        let dir = test_dir("playlist-pls");
+        let songs = exported_songs(&dir);
+        let songs: Vec<&Song> = songs.iter().collect();
+
+        // Read back from somewhere else, as the paths are absolute
+        let playlist = parse_pls(&pls_contents(&songs), Path::new("/elsewhere"));
+
+        assert_eq!(
+            entries(&playlist),
+            vec![
+                (
+                    dir.join("So What & <More> #1.wav"),
+                    Some("Miles Davis - So What & <More>".to_string())
+                ),
+                (dir.join("sub dir/été 100%.wav"), Some("Été".to_string())),
+                (dir.join("untagged.wav"), Some("untagged".to_string())),
+            ]
+        );
+        assert!(playlist.unresolved.is_empty());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn xspf_round_trip() {
+        let dir = test_dir("playlist-xspf");
+        let songs = exported_songs(&dir);
+        let songs: Vec<&Song> = songs.iter().collect();
+
+        let playlist = parse_xspf(&xspf_contents(&songs), Path::new("/elsewhere")).unwrap();
+
+        // Untagged titles are left out rather than made up from the file name
+        assert_eq!(
+            entries(&playlist),
+            vec![
+                (
+                    dir.join("So What & <More> #1.wav"),
+                    Some("Miles Davis - So What & <More>".to_string())
+                ),
+                (dir.join("sub dir/été 100%.wav"), Some("Été".to_string())),
+                (dir.join("untagged.wav"), None),
+            ]
+        );
+        assert!(playlist.unresolved.is_empty());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn cue_tracks_are_left_out_of_exports() {
+        let dir = test_dir("playlist-export-cue");
+        let mut songs = exported_songs(&dir);
+
+        let mut cue_song = songs[2].clone();
+        cue_song.set_cue_track(CueTrack {
+            number: 2,
+            title: Some("Second".to_string()),
+            performer: None,
+            album: None,
+            start: Duration::from_millis(500),
+            end: None,
+        });
+        songs.insert(1, cue_song);
+        let songs: Vec<&Song> = songs.iter().collect();
+
+        for format in [
+            PlaylistFormat::M3u,
+            PlaylistFormat::Pls,
+            PlaylistFormat::Xspf,
+        ] {
+            let path = dir.join(format!("queue.{}", format.extension()));
+            assert_eq!(write_playlist(&path, format, &songs).unwrap(), 1);
+
+            // Reading it back doesn't play the whole file in place of the track
+            let paths: Vec<PathBuf> = read_playlist(&path)
+                .unwrap()
+                .entries
+                .into_iter()
+                .map(|entry| entry.path)
+                .collect();
+            assert_eq!(
+                paths,
+                vec![
+                    dir.join("So What & <More> #1.wav"),
+                    dir.join("sub dir/été 100%.wav"),
+                    dir.join("untagged.wav"),
+                ]
+            );
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn cue_files_resolve_to_other_extensions() {
+        let dir = test_dir("playlist-cue-resolve");
+        write_wav(&dir.join("album.flac"));
+        fs::write(dir.join("album.cue"), "").unwrap();
+        fs::write(dir.join("album.log"), "").unwrap();
+
+        assert_eq!(
+            resolve_cue_file("album.wav", &dir),
+            Some(dir.join("album.flac"))
+        );
+        assert_eq!(
+            resolve_cue_file("album.flac", &dir),
+            Some(dir.join("album.flac"))
+        );
+        assert_eq!(resolve_cue_file("other.wav", &dir), None);
+
+        // The name as written wins over a converted copy
+        write_wav(&dir.join("album.wav"));
+        assert_eq!(
+            resolve_cue_file("album.wav", &dir),
+            Some(dir.join("album.wav"))
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn cue_tracks_point_into_their_files() {
+        let dir = test_dir("playlist-cue-parse");
+        write_wav(&dir.join("one.flac"));
+        write_wav(&dir.join("two.wav"));
+
+        let sheet = "FILE \"one.wav\" WAVE
+  TRACK 01 AUDIO
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    INDEX 01 00:00:30
+FILE \"missing.wav\" WAVE
+  TRACK 03 AUDIO
+    INDEX 01 00:00:00
+FILE \"two.wav\" WAVE
+  TRACK 04 AUDIO
+    INDEX 01 00:00:00
+";
+        let playlist = parse_cue(sheet, &dir);
+
+        let entries: Vec<(PathBuf, u32)> = playlist
+            .entries
+            .iter()
+            .map(|entry| (entry.path.clone(), entry.cue_track.as_ref().unwrap().number))
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                (dir.join("one.flac"), 1),
+                (dir.join("one.flac"), 2),
+                (dir.join("two.wav"), 4),
+            ]
+        );
+        assert_eq!(playlist.unresolved, vec!["missing.wav (not found)"]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use color_eyre::Result;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cue::CueTrack;
+use crate::xdg_dir;
+
+// Bumped whenever the layout of `PlayCounts` changes, older files are then ignored
+const PLAYS_VERSION: u32 = 1;
+
+const PLAYS_FILE_NAME: &str = "plays.json";
+
+// How far playback may run ahead of the clock between two checks, as the output
+// reports its position in chunks. Anything more was a seek
+const POSITION_DRIFT: Duration = Duration::from_millis(500);
+
+// How often each track was played. Kept apart from the library index, which gets
+// rebuilt from the tags now and then, while play counts only exist here
+#[derive(Serialize, Deserialize)]
+pub struct PlayCounts {
+    pub version: u32,
+
+    counts: BTreeMap<String, u32>,
+}
+
+impl PlayCounts {
+    pub fn new() -> Self {
+        Self {
+            version: PLAYS_VERSION,
+
+            counts: BTreeMap::new(),
+        }
+    }
+
+    fn file_path() -> PathBuf {
+        return xdg_dir("XDG_DATA_HOME", "~/.local/share").join(PLAYS_FILE_NAME);
+    }
+
+    // Missing, unreadable or outdated files all mean starting from no plays
+    pub fn load() -> Self {
+        let counts = fs::read_to_string(Self::file_path())
+            .ok()
+            .and_then(|contents| serde_json::from_str::<PlayCounts>(&contents).ok());
+
+        match counts {
+            Some(counts) if counts.version == PLAYS_VERSION => return counts,
+            _ => return Self::new(),
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::file_path();
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+
+        Ok(())
+    }
+
+    pub fn count(&self, path: &str, cue_track: Option<&CueTrack>) -> u32 {
+        return self
+            .counts
+            .get(&Self::key(path, cue_track))
+            .copied()
+            .unwrap_or(0);
+    }
+
+    pub fn record(&mut self, path: &str, cue_track: Option<&CueTrack>) -> () {
+        *self.counts.entry(Self::key(path, cue_track)).or_default() += 1;
+    }
+
+    // The tracks of a CUE sheet share their file, the track number tells them apart
+    fn key(path: &str, cue_track: Option<&CueTrack>) -> String {
+        match cue_track {
+            Some(track) => return format!("{}#{}", path, track.number),
+            None => return path.to_string(),
+        }
+    }
+}
+
+// How much of the current song was actually heard. Seeking and clicking along the
+// progress bar move the position without anything being heard, so only playback that
+// keeps pace with the clock adds up
+pub struct ListeningTime {
+    heard: Duration,
+
+    position: Duration,
+    checked_at: Instant,
+}
+
+impl ListeningTime {
+    pub fn new() -> Self {
+        Self {
+            heard: Duration::ZERO,
+
+            position: Duration::ZERO,
+            checked_at: Instant::now(),
+        }
+    }
+
+    // Starts over for the next song
+    pub fn restart(&mut self, position: Duration, now: Instant) -> () {
+        self.heard = Duration::ZERO;
+        self.position = position;
+        self.checked_at = now;
+    }
+
+    pub fn update(&mut self, position: Duration, now: Instant) -> () {
+        let elapsed = now.saturating_duration_since(self.checked_at);
+
+        // Paused playback doesn't move, seeking back moves the wrong way
+        if position > self.position && position - self.position <= elapsed + POSITION_DRIFT {
+            self.heard += position - self.position;
+        }
+
+        self.position = position;
+        self.checked_at = now;
+    }
+
+    pub fn heard(&self) -> Duration {
+        return self.heard;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    // Plays from `listening`'s position on, checking once a second
+    fn play(listening: &mut ListeningTime, now: &mut Instant, seconds: u32) -> () {
+        for _ in 0..seconds {
+            *now += SECOND;
+            listening.update(listening.position + SECOND, *now);
+        }
+    }
+
+    #[test]
+    fn playback_adds_up() {
+        let mut now = Instant::now();
+        let mut listening = ListeningTime::new();
+        listening.restart(Duration::ZERO, now);
+
+        play(&mut listening, &mut now, 30);
+        assert_eq!(listening.heard(), 30 * SECOND);
+
+        // Paused
+        now += 60 * SECOND;
+        listening.update(30 * SECOND, now);
+        assert_eq!(listening.heard(), 30 * SECOND);
+
+        play(&mut listening, &mut now, 10);
+        assert_eq!(listening.heard(), 40 * SECOND);
+
+        listening.restart(Duration::ZERO, now);
+        assert_eq!(listening.heard(), Duration::ZERO);
+    }
+
+    #[test]
+    fn seeks_are_not_heard() {
+        let mut now = Instant::now();
+        let mut listening = ListeningTime::new();
+        listening.restart(Duration::ZERO, now);
+
+        play(&mut listening, &mut now, 5);
+
+        // Clicking near the end of the progress bar
+        now += SECOND / 10;
+        listening.update(200 * SECOND, now);
+        assert_eq!(listening.heard(), 5 * SECOND);
+
+        play(&mut listening, &mut now, 5);
+        assert_eq!(listening.heard(), 10 * SECOND);
+
+        // Seeking back, then hearing the same part again
+        now += SECOND / 10;
+        listening.update(100 * SECOND, now);
+        play(&mut listening, &mut now, 5);
+        assert_eq!(listening.heard(), 15 * SECOND);
+    }
+}
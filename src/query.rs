@@ -0,0 +1,477 @@
+use color_eyre::Result;
+use color_eyre::eyre::{bail, eyre};
+
+use crate::library::LibraryTrack;
+
+// Queries pick tracks out of the library, like
+//
+//     genre:jazz year:1955..1965 rating>=4 -artist:"Kenny G" played<3
+//
+// Every term has to match. Text fields are matched case-insensitively, with `:` for containing
+// the value and `=` for being it. Number fields take `:` or `=` with a number or a range like
+// `1955..1965`, `1955..` or `..1965`, or `<`, `<=`, `>` and `>=` with a number. Words without
+// a field have to appear in the title, artist, album or path. A `-` in front turns a term around,
+// and values with spaces go in double quotes
+
+const TEXT_FIELDS: [(&str, TextField); 6] = [
+    ("title", TextField::Title),
+    ("artist", TextField::Artist),
+    ("albumartist", TextField::AlbumArtist),
+    ("album", TextField::Album),
+    ("genre", TextField::Genre),
+    ("path", TextField::Path),
+];
+
+const NUMBER_FIELDS: [(&str, NumberField); 5] = [
+    ("year", NumberField::Year),
+    ("rating", NumberField::Rating),
+    ("played", NumberField::Played),
+    ("track", NumberField::Track),
+    ("disc", NumberField::Disc),
+];
+
+// Longer operators first, so that `<=` isn't taken for `<`
+const OPERATORS: [(&str, Operator); 6] = [
+    ("<=", Operator::AtMost),
+    (">=", Operator::AtLeast),
+    (":", Operator::Matches),
+    ("=", Operator::Is),
+    ("<", Operator::Below),
+    (">", Operator::Above),
+];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum TextField {
+    Title,
+    Artist,
+    AlbumArtist,
+    Album,
+    Genre,
+    Path,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum NumberField {
+    Year,
+    // Stars out of 5, unrated tracks have 0
+    Rating,
+    Played,
+    Track,
+    Disc,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Operator {
+    Matches,
+    Is,
+    Below,
+    AtMost,
+    Above,
+    AtLeast,
+}
+
+#[derive(PartialEq, Debug)]
+enum Test {
+    Anywhere(String),
+    Contains(TextField, String),
+    Is(TextField, String),
+    // Both ends are included, a missing one is open
+    Between(NumberField, Option<u32>, Option<u32>),
+}
+
+#[derive(PartialEq, Debug)]
+struct Term {
+    negated: bool,
+    test: Test,
+}
+
+#[derive(PartialEq, Debug)]
+pub struct Query {
+    terms: Vec<Term>,
+}
+
+impl Query {
+    // Errors say which column of the text they're about, counting from 1
+    pub fn parse(text: &str) -> Result<Self> {
+        let chars: Vec<char> = text.chars().collect();
+        let mut terms = Vec::new();
+        let mut position = 0;
+
+        loop {
+            while chars.get(position).is_some_and(|c| c.is_whitespace()) {
+                position += 1;
+            }
+            if position >= chars.len() {
+                break;
+            }
+
+            let negated = chars[position] == '-'
+                && chars.get(position + 1).is_some_and(|c| !c.is_whitespace());
+            if negated {
+                position += 1;
+            }
+
+            // A field is a name that's followed right away by an operator
+            let name_start = position;
+            let name_end = name_start
+                + chars[name_start..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_alphabetic())
+                    .count();
+
+            let test = match operator_at(&chars, name_end) {
+                Some((symbol, operator)) if name_end > name_start => {
+                    let name: String = chars[name_start..name_end].iter().collect();
+                    position = name_end + symbol.len();
+
+                    let value_column = position + 1;
+                    let value = read_value(&chars, &mut position)?;
+                    if value.is_empty() {
+                        bail!(
+                            "column {}: '{}{}' is missing a value",
+                            value_column,
+                            name,
+                            symbol
+                        );
+                    }
+
+                    field_test(
+                        &name,
+                        name_start + 1,
+                        operator,
+                        symbol,
+                        &value,
+                        value_column,
+                    )?
+                }
+                _ => Test::Anywhere(read_value(&chars, &mut position)?.to_lowercase()),
+            };
+
+            terms.push(Term { negated, test });
+        }
+
+        return Ok(Self { terms });
+    }
+
+    pub fn matches(&self, track: &LibraryTrack, plays: u32) -> bool {
+        return self
+            .terms
+            .iter()
+            .all(|term| term.negated != test_track(&term.test, track, plays));
+    }
+}
+
+fn operator_at(chars: &[char], position: usize) -> Option<(&'static str, Operator)> {
+    return OPERATORS.into_iter().find(|(symbol, _)| {
+        symbol
+            .chars()
+            .enumerate()
+            .all(|(i, c)| chars.get(position + i) == Some(&c))
+    });
+}
+
+// A quoted value runs up to the closing quote, anything else up to the next space
+fn read_value(chars: &[char], position: &mut usize) -> Result<String> {
+    if chars.get(*position) == Some(&'"') {
+        let quote_column = *position + 1;
+        let start = *position + 1;
+
+        let Some(length) = chars[start..].iter().position(|c| *c == '"') else {
+            bail!("column {}: the quote is never closed", quote_column);
+        };
+
+        *position = start + length + 1;
+        return Ok(chars[start..start + length].iter().collect());
+    }
+
+    let start = *position;
+    while chars.get(*position).is_some_and(|c| !c.is_whitespace()) {
+        *position += 1;
+    }
+
+    return Ok(chars[start..*position].iter().collect());
+}
+
+fn field_test(
+    name: &str,
+    name_column: usize,
+    operator: Operator,
+    symbol: &str,
+    value: &str,
+    value_column: usize,
+) -> Result<Test> {
+    let lowercase_name = name.to_lowercase();
+
+    if let Some((_, field)) = TEXT_FIELDS
+        .iter()
+        .find(|(field_name, _)| *field_name == lowercase_name)
+    {
+        let value = value.to_lowercase();
+
+        match operator {
+            Operator::Matches => return Ok(Test::Contains(*field, value)),
+            Operator::Is => return Ok(Test::Is(*field, value)),
+            _ => bail!(
+                "column {}: '{}' is text, which only goes with ':' or '=', not '{}'",
+                name_column,
+                name,
+                symbol
+            ),
+        }
+    }
+
+    let Some((_, field)) = NUMBER_FIELDS
+        .iter()
+        .find(|(field_name, _)| *field_name == lowercase_name)
+    else {
+        let field_names: Vec<&str> = TEXT_FIELDS
+            .iter()
+            .map(|(field_name, _)| *field_name)
+            .chain(NUMBER_FIELDS.iter().map(|(field_name, _)| *field_name))
+            .collect();
+
+        bail!(
+            "column {}: there's no field called '{}', try one of {}",
+            name_column,
+            name,
+            field_names.join(", ")
+        );
+    };
+
+    let number = |text: &str| {
+        text.parse::<u32>().map_err(|_| match operator {
+            Operator::Matches | Operator::Is => eyre!(
+                "column {}: '{}' takes a whole number or a range like 1955..1965, not '{}'",
+                value_column,
+                name,
+                value
+            ),
+            _ => eyre!(
+                "column {}: '{}' takes a whole number, not '{}'",
+                value_column,
+                name,
+                value
+            ),
+        })
+    };
+
+    let (low, high) = match operator {
+        Operator::Matches | Operator::Is => match value.split_once("..") {
+            Some((low, high)) => {
+                if low.is_empty() && high.is_empty() {
+                    return Err(number(value).unwrap_err());
+                }
+                let low = (!low.is_empty()).then(|| number(low)).transpose()?;
+                let high = (!high.is_empty()).then(|| number(high)).transpose()?;
+                (low, high)
+            }
+            None => {
+                let number = number(value)?;
+                (Some(number), Some(number))
+            }
+        },
+        Operator::Below => match number(value)?.checked_sub(1) {
+            Some(high) => (None, Some(high)),
+            // Nothing is below 0, a range that ends before it starts matches nothing
+            None => (Some(1), Some(0)),
+        },
+        Operator::AtMost => (None, Some(number(value)?)),
+        Operator::Above => (Some(number(value)?.saturating_add(1)), None),
+        Operator::AtLeast => (Some(number(value)?), None),
+    };
+
+    return Ok(Test::Between(*field, low, high));
+}
+
+fn test_track(test: &Test, track: &LibraryTrack, plays: u32) -> bool {
+    match test {
+        Test::Anywhere(word) => {
+            return [
+                Some(track.title.as_str()),
+                track.artist.as_deref(),
+                track.album_artist.as_deref(),
+                track.album.as_deref(),
+                Some(track.path.as_str()),
+            ]
+            .into_iter()
+            .flatten()
+            .any(|text| text.to_lowercase().contains(word));
+        }
+        Test::Contains(field, value) => {
+            return text_of(track, *field).is_some_and(|text| text.to_lowercase().contains(value));
+        }
+        Test::Is(field, value) => {
+            return text_of(track, *field).is_some_and(|text| text.to_lowercase() == *value);
+        }
+        Test::Between(field, low, high) => {
+            return number_of(track, *field, plays).is_some_and(|number| {
+                low.is_none_or(|low| number >= low) && high.is_none_or(|high| number <= high)
+            });
+        }
+    }
+}
+
+fn text_of(track: &LibraryTrack, field: TextField) -> Option<&str> {
+    match field {
+        TextField::Title => return Some(&track.title),
+        TextField::Artist => return track.artist.as_deref().or(track.album_artist.as_deref()),
+        TextField::AlbumArtist => return track.album_artist.as_deref().or(track.artist.as_deref()),
+        TextField::Album => return track.album.as_deref(),
+        TextField::Genre => return track.genre.as_deref(),
+        TextField::Path => return Some(&track.path),
+    }
+}
+
+fn number_of(track: &LibraryTrack, field: NumberField, plays: u32) -> Option<u32> {
+    match field {
+        NumberField::Year => return track.year(),
+        NumberField::Rating => return Some(track.rating.unwrap_or(0) as u32),
+        NumberField::Played => return Some(plays),
+        NumberField::Track => return track.track_number,
+        NumberField::Disc => return track.disc_number,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(artist: &str, genre: &str, date: &str, rating: Option<u8>) -> LibraryTrack {
+        LibraryTrack {
+            path: format!("/music/{}/track.flac", artist),
+            cue_track: None,
+
+            title: "Track".to_string(),
+            artist: Some(artist.to_string()),
+            album: Some("Album".to_string()),
+            album_artist: None,
+            genre: Some(genre.to_string()),
+            date: Some(date.to_string()),
+            track_number: Some(1),
+            disc_number: None,
+            rating,
+
+            duration_secs: 60.0,
+        }
+    }
+
+    fn error(text: &str) -> String {
+        return Query::parse(text).unwrap_err().to_string();
+    }
+
+    #[test]
+    fn parses_the_example() {
+        let query =
+            Query::parse(r#"genre:jazz year:1955..1965 rating>=4 -artist:"Kenny G" played<3"#)
+                .unwrap();
+
+        assert_eq!(
+            query.terms,
+            vec![
+                Term {
+                    negated: false,
+                    test: Test::Contains(TextField::Genre, "jazz".to_string()),
+                },
+                Term {
+                    negated: false,
+                    test: Test::Between(NumberField::Year, Some(1955), Some(1965)),
+                },
+                Term {
+                    negated: false,
+                    test: Test::Between(NumberField::Rating, Some(4), None),
+                },
+                Term {
+                    negated: true,
+                    test: Test::Contains(TextField::Artist, "kenny g".to_string()),
+                },
+                Term {
+                    negated: false,
+                    test: Test::Between(NumberField::Played, None, Some(2)),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn every_term_has_to_match() {
+        let query =
+            Query::parse(r#"genre:jazz year:1955..1965 rating>=4 -artist:"Kenny G" played<3"#)
+                .unwrap();
+
+        assert!(query.matches(&track("Miles Davis", "Cool Jazz", "1959-08-17", Some(5)), 0));
+        // Played too often
+        assert!(!query.matches(&track("Miles Davis", "Jazz", "1959", Some(5)), 3));
+        // Rated too low, or not at all
+        assert!(!query.matches(&track("Miles Davis", "Jazz", "1959", Some(3)), 0));
+        assert!(!query.matches(&track("Miles Davis", "Jazz", "1959", None), 0));
+        // Too late, or no year at all
+        assert!(!query.matches(&track("Miles Davis", "Jazz", "1970", Some(5)), 0));
+        assert!(!query.matches(&track("Miles Davis", "Jazz", "", Some(5)), 0));
+        // Left out on purpose
+        assert!(!query.matches(&track("Kenny G", "Jazz", "1959", Some(5)), 0));
+    }
+
+    #[test]
+    fn words_without_a_field_match_anywhere() {
+        let query = Query::parse("miles -live").unwrap();
+
+        assert!(query.matches(&track("Miles Davis", "Jazz", "1959", None), 0));
+        assert!(!query.matches(&track("Miles Davis Live", "Jazz", "1959", None), 0));
+        assert!(!query.matches(&track("John Coltrane", "Jazz", "1959", None), 0));
+
+        // An empty query matches everything
+        assert!(
+            Query::parse("  ")
+                .unwrap()
+                .matches(&track("Anyone", "Any", "", None), 0)
+        );
+    }
+
+    #[test]
+    fn exact_text_and_open_ranges() {
+        let bebop = track("Charlie Parker", "Bebop", "1949", None);
+
+        assert!(Query::parse("genre=bebop").unwrap().matches(&bebop, 0));
+        assert!(!Query::parse("genre=bop").unwrap().matches(&bebop, 0));
+        assert!(Query::parse("genre:bop").unwrap().matches(&bebop, 0));
+
+        assert!(Query::parse("year:..1950").unwrap().matches(&bebop, 0));
+        assert!(!Query::parse("year:1950..").unwrap().matches(&bebop, 0));
+        assert!(
+            Query::parse("year:1949 year>1948 year<=1949")
+                .unwrap()
+                .matches(&bebop, 0)
+        );
+        assert!(!Query::parse("played<0").unwrap().matches(&bebop, 0));
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        assert_eq!(
+            error("genre:jazz ratng>=4"),
+            "column 12: there's no field called 'ratng', try one of title, artist, albumartist, \
+             album, genre, path, year, rating, played, track, disc"
+        );
+        assert_eq!(
+            error("year:fifties"),
+            "column 6: 'year' takes a whole number or a range like 1955..1965, not 'fifties'"
+        );
+        assert_eq!(
+            error("rating>=four"),
+            "column 9: 'rating' takes a whole number, not 'four'"
+        );
+        assert_eq!(
+            error("artist>Kenny"),
+            "column 1: 'artist' is text, which only goes with ':' or '=', not '>'"
+        );
+        assert_eq!(
+            error("-artist:\"Kenny G"),
+            "column 9: the quote is never closed"
+        );
+        assert_eq!(
+            error("genre: jazz"),
+            "column 7: 'genre:' is missing a value"
+        );
+        assert!(Query::parse("year:..").is_err());
+    }
+}
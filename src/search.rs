@@ -0,0 +1,407 @@
+use std::fs::read_dir;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use lofty::file::FileType;
+
+use crate::cue::{CueTrack, is_cue_sheet};
+use crate::library::LibraryTrack;
+use crate::playlist::{Playlist, PlaylistEntry};
+
+// Points for every character of the query that matched
+const MATCH_SCORE: i32 = 1;
+// Extra points for a match right after the previous one, growing with every further one
+const STREAK_BONUS: i32 = 4;
+// Extra points for a match at the start of a word
+const WORD_START_BONUS: i32 = 6;
+// Taken off for every character skipped between two matches, up to `MAX_GAP_PENALTY`
+const GAP_PENALTY: i32 = 1;
+const MAX_GAP_PENALTY: i32 = 8;
+
+// Matches in the title count most, matches in the path least
+const TITLE_WEIGHT: i32 = 3;
+const TAG_WEIGHT: i32 = 2;
+const PATH_WEIGHT: i32 = 0;
+
+// Something the search can find, a track of the library or a file under a directory
+pub struct SearchItem {
+    pub title: String,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub path: PathBuf,
+    // Tracks from CUE sheets only play a part of the file
+    pub cue_track: Option<CueTrack>,
+
+    // Lower case versions of the fields, with how much a match in each is worth
+    haystacks: Vec<(String, i32)>,
+}
+
+impl SearchItem {
+    fn new(
+        title: String,
+        artist: Option<String>,
+        album: Option<String>,
+        path: PathBuf,
+        cue_track: Option<CueTrack>,
+    ) -> Self {
+        let mut haystacks = vec![(title.to_lowercase(), TITLE_WEIGHT)];
+        haystacks.extend(
+            [&artist, &album]
+                .into_iter()
+                .flatten()
+                .map(|tag| (tag.to_lowercase(), TAG_WEIGHT)),
+        );
+        haystacks.push((path.to_string_lossy().to_lowercase(), PATH_WEIGHT));
+
+        Self {
+            title,
+            artist,
+            album,
+            path,
+            cue_track,
+
+            haystacks,
+        }
+    }
+
+    pub fn from_track(track: &LibraryTrack) -> Self {
+        return Self::new(
+            track.title.clone(),
+            track.artist.clone().or(track.album_artist.clone()),
+            track.album.clone(),
+            PathBuf::from(&track.path),
+            track.cue_track.clone(),
+        );
+    }
+
+    // Files found without a library only have their name to go by
+    pub fn from_path(path: PathBuf) -> Self {
+        let title = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        return Self::new(title, None, None, path, None);
+    }
+
+    // What choosing the item adds to the queue. Files that weren't looked into yet
+    // go through the same checks as files from the file selector
+    pub fn playlist(&self) -> Playlist {
+        if self.cue_track.is_none() {
+            return Playlist::from_file(self.path.clone());
+        }
+
+        let mut playlist = Playlist::new();
+        playlist.entries.push(PlaylistEntry {
+            path: self.path.clone(),
+            title: None,
+            cue_track: self.cue_track.clone(),
+        });
+
+        return playlist;
+    }
+
+    // Every word of the query has to match one of the fields, the best match of each counts
+    fn score(&self, query: &str) -> Option<i32> {
+        let mut total = 0;
+
+        for term in query.split_whitespace() {
+            total += self
+                .haystacks
+                .iter()
+                .filter_map(|(haystack, weight)| {
+                    fuzzy_score(term, haystack).map(|score| score + weight)
+                })
+                .max()?;
+        }
+
+        return Some(total);
+    }
+}
+
+// The files under the directory that look like music or CUE sheets, going by their extensions.
+// That's only a guess, reading the header of every file would take far too long
+pub fn items_in_tree(dir_path: &Path) -> Vec<SearchItem> {
+    let mut items = Vec::new();
+    add_items_in_tree(dir_path, &mut items);
+
+    return items;
+}
+
+fn add_items_in_tree(dir_path: &Path, items: &mut Vec<SearchItem>) -> () {
+    let Ok(entries) = read_dir(dir_path) else {
+        return;
+    };
+
+    let mut entries: Vec<_> = entries.filter_map(|res| res.ok()).collect();
+    entries.sort_by_key(|entry| entry.path());
+
+    for entry in entries {
+        let path = entry.path();
+
+        // Symlinked directories aren't followed, they could lead back up the tree
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => add_items_in_tree(&path, items),
+            _ => {
+                let is_music = path
+                    .extension()
+                    .is_some_and(|ext| FileType::from_ext(ext).is_some());
+
+                if is_music || is_cue_sheet(&path) {
+                    items.push(SearchItem::from_path(path));
+                }
+            }
+        }
+    }
+}
+
+// How well `query` matches `text`, both in lower case. `None` unless every character of the
+// query appears in the text, in order. Every place the first character appears is tried as
+// the start, the rest of the query then takes the first match it finds
+pub fn fuzzy_score(query: &str, text: &str) -> Option<i32> {
+    let query: Vec<char> = query.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let Some(first) = query.first() else {
+        return Some(0);
+    };
+
+    return text
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| *c == first)
+        .filter_map(|(start, _)| score_from(&query, &text, start))
+        .max();
+}
+
+fn score_from(query: &[char], text: &[char], start: usize) -> Option<i32> {
+    let mut score = 0;
+    let mut streak = 0;
+    let mut last_match: Option<usize> = None;
+    let mut position = start;
+
+    for c in query {
+        let found = position + text[position..].iter().position(|t| t == c)?;
+
+        score += MATCH_SCORE;
+
+        let word_start = found == 0 || !text[found - 1].is_alphanumeric();
+        if word_start {
+            score += WORD_START_BONUS;
+        }
+
+        match last_match {
+            Some(last) if last + 1 == found => {
+                streak += 1;
+                score += STREAK_BONUS * streak;
+            }
+            Some(last) => {
+                streak = 0;
+                score -= (GAP_PENALTY * (found - last - 1) as i32).min(MAX_GAP_PENALTY);
+            }
+            None => {}
+        }
+
+        last_match = Some(found);
+        position = found + 1;
+    }
+
+    return Some(score);
+}
+
+// The query typed so far and the items that match it, best first
+pub struct Search {
+    query: String,
+    items: Vec<SearchItem>,
+
+    // Indices into `items`
+    results: Vec<usize>,
+    selected_result: usize,
+}
+
+impl Search {
+    pub fn new(items: Vec<SearchItem>) -> Self {
+        let mut search = Self {
+            query: String::new(),
+            items,
+
+            results: Vec::new(),
+            selected_result: 0,
+        };
+        search.rank(None);
+
+        return search;
+    }
+
+    // Swaps in items that took a while to find, keeping the query
+    pub fn set_items(&mut self, items: Vec<SearchItem>) -> () {
+        self.items = items;
+        self.rank(None);
+    }
+
+    pub fn query(&self) -> &str {
+        return &self.query;
+    }
+
+    pub fn items(&self) -> &Vec<SearchItem> {
+        return &self.items;
+    }
+
+    // Only items that matched before can match a longer query, so only those are looked at
+    pub fn push(&mut self, c: char) -> () {
+        self.query.push(c);
+
+        let candidates = mem::take(&mut self.results);
+        self.rank(Some(candidates));
+    }
+
+    pub fn pop(&mut self) -> () {
+        if self.query.pop().is_some() {
+            self.rank(None);
+        }
+    }
+
+    pub fn results(&self) -> impl Iterator<Item = &SearchItem> {
+        return self.results.iter().map(|index| &self.items[*index]);
+    }
+
+    pub fn result_count(&self) -> usize {
+        return self.results.len();
+    }
+
+    pub fn selected_result(&self) -> usize {
+        return self.selected_result;
+    }
+
+    pub fn selected_item(&self) -> Option<&SearchItem> {
+        let index = self.results.get(self.selected_result)?;
+        return Some(&self.items[*index]);
+    }
+
+    pub fn move_up(&mut self) -> () {
+        self.selected_result = self.selected_result.saturating_sub(1);
+    }
+
+    pub fn move_down(&mut self) -> () {
+        if self.selected_result + 1 < self.results.len() {
+            self.selected_result += 1;
+        }
+    }
+
+    // An empty query matches everything, in the order the items came in.
+    // Equally good matches keep that order too
+    fn rank(&mut self, candidates: Option<Vec<usize>>) -> () {
+        let candidates = candidates.unwrap_or_else(|| (0..self.items.len()).collect());
+        let query = self.query.to_lowercase();
+
+        let mut scored: Vec<(usize, i32)> = candidates
+            .into_iter()
+            .filter_map(|index| self.items[index].score(&query).map(|score| (index, score)))
+            .collect();
+        scored.sort_by_key(|(index, score)| (-score, *index));
+
+        self.results = scored.into_iter().map(|(index, _)| index).collect();
+        self.selected_result = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(title: &str, artist: &str, album: &str) -> SearchItem {
+        return SearchItem::new(
+            title.to_string(),
+            Some(artist.to_string()),
+            Some(album.to_string()),
+            PathBuf::from(format!("/music/{}/{}/{}.flac", artist, album, title)),
+            None,
+        );
+    }
+
+    fn titles(search: &Search) -> Vec<&str> {
+        return search.results().map(|item| item.title.as_str()).collect();
+    }
+
+    #[test]
+    fn characters_have_to_appear_in_order() {
+        assert!(fuzzy_score("kob", "kind of blue").is_some());
+        assert!(fuzzy_score("bok", "kind of blue").is_none());
+        assert!(fuzzy_score("x", "kind of blue").is_none());
+        assert_eq!(fuzzy_score("", "kind of blue"), Some(0));
+    }
+
+    #[test]
+    fn word_starts_and_streaks_score_higher() {
+        let word_starts = fuzzy_score("kob", "kind of blue").unwrap();
+        let scattered = fuzzy_score("kob", "kingdom boredom").unwrap();
+        assert!(word_starts > scattered);
+
+        let streak = fuzzy_score("blue", "kind of blue").unwrap();
+        let spread = fuzzy_score("blue", "be lucky everyone").unwrap();
+        assert!(streak > spread);
+
+        // The later, better match wins over the first one
+        let later = fuzzy_score("blue", "b - blue").unwrap();
+        assert_eq!(later, fuzzy_score("blue", "blue").unwrap());
+    }
+
+    #[test]
+    fn every_word_has_to_match_some_field() {
+        let mut search = Search::new(vec![
+            item("So What", "Miles Davis", "Kind of Blue"),
+            item("Blue in Green", "Miles Davis", "Kind of Blue"),
+            item("Blue Train", "John Coltrane", "Blue Train"),
+        ]);
+
+        for c in "miles blue".chars() {
+            search.push(c);
+        }
+        assert_eq!(titles(&search), vec!["Blue in Green", "So What"]);
+
+        for c in " green".chars() {
+            search.push(c);
+        }
+        assert_eq!(titles(&search), vec!["Blue in Green"]);
+    }
+
+    #[test]
+    fn title_matches_come_first() {
+        let mut search = Search::new(vec![
+            item("Intro", "Train", "Live"),
+            item("Train", "Someone", "Songs"),
+        ]);
+        assert_eq!(titles(&search), vec!["Intro", "Train"]);
+
+        for c in "train".chars() {
+            search.push(c);
+        }
+        assert_eq!(titles(&search), vec!["Train", "Intro"]);
+    }
+
+    #[test]
+    fn deleting_brings_results_back() {
+        let mut search = Search::new(vec![
+            item("So What", "Miles Davis", "Kind of Blue"),
+            item("Blue Train", "John Coltrane", "Blue Train"),
+        ]);
+
+        for c in "what".chars() {
+            search.push(c);
+        }
+        search.move_down();
+        assert_eq!(search.result_count(), 1);
+        assert_eq!(search.selected_result(), 0);
+
+        for _ in 0..4 {
+            search.pop();
+        }
+        search.pop();
+        assert_eq!(search.query(), "");
+        assert_eq!(search.result_count(), 2);
+
+        search.move_down();
+        search.move_down();
+        assert_eq!(search.selected_item().unwrap().title, "Blue Train");
+    }
+}
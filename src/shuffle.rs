@@ -0,0 +1,235 @@
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+// The order in which queue entries get played.
+// Positions index into the order, which in turn holds indices into the queue
+pub struct PlayOrder {
+    order: Vec<usize>,
+    // The order the user put the queue in, restored when shuffle gets turned off
+    original: Vec<usize>,
+    shuffled: bool,
+
+    rng: StdRng,
+}
+
+impl PlayOrder {
+    // A seed makes the shuffled order reproducible
+    pub fn new(seed: Option<u64>) -> Self {
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_os_rng(),
+        };
+
+        Self {
+            order: Vec::new(),
+            original: Vec::new(),
+            shuffled: false,
+
+            rng,
+        }
+    }
+
+    pub fn is_shuffled(&self) -> bool {
+        return self.shuffled;
+    }
+
+    pub fn get(&self, position: usize) -> Option<usize> {
+        return self.order.get(position).copied();
+    }
+
+    pub fn position_of(&self, queue_index: usize) -> Option<usize> {
+        return self.order.iter().position(|index| *index == queue_index);
+    }
+
+    pub fn as_slice(&self) -> &[usize] {
+        return &self.order;
+    }
+
+    pub fn original(&self) -> &[usize] {
+        return &self.original;
+    }
+
+    // Takes over a previously saved order, unless it doesn't fit a queue of `len` entries
+    pub fn restore(
+        &mut self,
+        order: Vec<usize>,
+        original: Vec<usize>,
+        shuffled: bool,
+        len: usize,
+    ) -> bool {
+        let is_permutation = |indices: &Vec<usize>| {
+            let mut sorted = indices.clone();
+            sorted.sort_unstable();
+            sorted.into_iter().eq(0..len)
+        };
+
+        if !is_permutation(&order) || !is_permutation(&original) {
+            return false;
+        }
+
+        self.order = order;
+        self.original = original;
+        self.shuffled = shuffled;
+
+        return true;
+    }
+
+    // Adds the entry that was just pushed onto the queue. While shuffled it lands somewhere
+    // among the tracks from `upcoming` onwards, so that the history stays as it is
+    pub fn push(&mut self, upcoming: usize) -> () {
+        let queue_index = self.order.len();
+        self.original.push(queue_index);
+
+        if self.shuffled {
+            let upcoming = upcoming.min(self.order.len());
+            let position = self.rng.random_range(upcoming..=self.order.len());
+            self.order.insert(position, queue_index);
+        } else {
+            self.order.push(queue_index);
+        }
+    }
+
+    // Removes the entry at `position` and returns the queue index it held.
+    // Queue indices after it move down by one, just like they do in the queue itself
+    pub fn remove(&mut self, position: usize) -> usize {
+        let queue_index = self.order.remove(position);
+        self.original.retain(|index| *index != queue_index);
+
+        for index in self.order.iter_mut().chain(self.original.iter_mut()) {
+            if *index > queue_index {
+                *index -= 1;
+            }
+        }
+
+        return queue_index;
+    }
+
+    // Moves the entry at `from` so that it ends up at `to`.
+    // Outside of shuffle this also changes the order that unshuffling goes back to
+    pub fn move_entry(&mut self, from: usize, to: usize) -> () {
+        let queue_index = self.order.remove(from);
+        self.order.insert(to, queue_index);
+
+        if !self.shuffled {
+            self.original = self.order.clone();
+        }
+    }
+
+    pub fn clear(&mut self) -> () {
+        self.order.clear();
+        self.original.clear();
+    }
+
+    // Shuffles everything from `upcoming` onwards, keeping what was already played in place
+    pub fn shuffle(&mut self, upcoming: usize) -> () {
+        let upcoming = upcoming.min(self.order.len());
+        self.order[upcoming..].shuffle(&mut self.rng);
+        self.shuffled = true;
+    }
+
+    // Goes back to the order from before shuffling
+    pub fn unshuffle(&mut self) -> () {
+        self.order = self.original.clone();
+        self.shuffled = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ops::Range;
+
+    const SEED: u64 = 42;
+
+    fn play_order(len: usize) -> PlayOrder {
+        let mut play_order = PlayOrder::new(Some(SEED));
+        for _ in 0..len {
+            play_order.push(0);
+        }
+
+        return play_order;
+    }
+
+    fn is_permutation(indices: &[usize], of: Range<usize>) -> bool {
+        let mut sorted = indices.to_vec();
+        sorted.sort_unstable();
+
+        return sorted.into_iter().eq(of);
+    }
+
+    #[test]
+    fn a_seed_makes_the_order_reproducible() {
+        let mut first = play_order(20);
+        let mut second = play_order(20);
+        first.shuffle(0);
+        second.shuffle(0);
+
+        assert_eq!(first.as_slice(), second.as_slice());
+        assert_ne!(first.as_slice(), play_order(20).as_slice());
+        assert!(is_permutation(first.as_slice(), 0..20));
+    }
+
+    #[test]
+    fn played_tracks_and_the_current_one_stay_put() {
+        let mut play_order = play_order(20);
+        // The fourth track is playing
+        play_order.shuffle(4);
+
+        assert_eq!(play_order.as_slice()[..4], [0, 1, 2, 3]);
+        assert!(is_permutation(&play_order.as_slice()[4..], 4..20));
+        assert!(play_order.is_shuffled());
+    }
+
+    #[test]
+    fn unshuffling_continues_from_the_current_track() {
+        let mut play_order = play_order(20);
+        play_order.shuffle(0);
+        let current = play_order.get(7).unwrap();
+
+        play_order.unshuffle();
+
+        assert_eq!(play_order.as_slice(), (0..20).collect::<Vec<_>>());
+        assert_eq!(play_order.position_of(current), Some(current));
+        assert!(!play_order.is_shuffled());
+    }
+
+    #[test]
+    fn tracks_added_while_shuffled_land_among_the_upcoming_ones() {
+        let mut play_order = play_order(10);
+        play_order.shuffle(0);
+        let played = play_order.as_slice()[..6].to_vec();
+
+        for _ in 0..10 {
+            play_order.push(6);
+        }
+
+        assert_eq!(play_order.as_slice()[..6], played);
+        assert!(is_permutation(play_order.as_slice(), 0..20));
+        // Unshuffling puts them after the tracks that were there before
+        play_order.unshuffle();
+        assert_eq!(play_order.as_slice(), (0..20).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn the_history_stays_as_it_was_played() {
+        let mut play_order = play_order(20);
+        play_order.shuffle(0);
+        // Five tracks were played, the sixth is up next
+        let played = play_order.as_slice()[..5].to_vec();
+
+        // Adding and moving tracks only touches what's ahead
+        play_order.push(5);
+        play_order.push(5);
+        play_order.move_entry(12, 6);
+
+        // Going back from the fifth track visits the played ones in reverse
+        let walked_back: Vec<usize> = (0..5)
+            .rev()
+            .map(|position| play_order.get(position).unwrap())
+            .collect();
+        let expected: Vec<usize> = played.into_iter().rev().collect();
+        assert_eq!(walked_back, expected);
+    }
+}
@@ -0,0 +1,93 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use color_eyre::Result;
+use color_eyre::eyre::{WrapErr, eyre};
+
+use crate::browser::{Grouping, sorted_tracks};
+use crate::library::Library;
+use crate::playlist::Playlist;
+use crate::plays::PlayCounts;
+use crate::query::Query;
+use crate::xdg_dir;
+
+const SMART_PLAYLISTS_FILE_NAME: &str = "smart-playlists.txt";
+
+// A named query, from a line like `Late jazz = genre:jazz year:1955..1965` of the
+// smart playlists file
+pub struct SmartPlaylist {
+    pub name: String,
+    pub definition: String,
+    // Playlists that don't parse are still listed, with what's wrong with them
+    pub query: Result<Query>,
+}
+
+impl SmartPlaylist {
+    pub fn file_path() -> PathBuf {
+        return xdg_dir("XDG_CONFIG_HOME", "~/.config").join(SMART_PLAYLISTS_FILE_NAME);
+    }
+
+    // Blank lines and lines starting with `#` are skipped. A missing file means no playlists
+    pub fn load_all() -> Result<Vec<Self>> {
+        let path = Self::file_path();
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(error) => {
+                return Err(error).wrap_err(format!("couldn't read {}", path.display()));
+            }
+        };
+
+        return Ok(Self::parse_all(&contents));
+    }
+
+    fn parse_all(contents: &str) -> Vec<Self> {
+        let mut playlists = Vec::new();
+
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let playlist = match line.split_once('=') {
+                Some((name, definition)) if !name.trim().is_empty() => Self {
+                    name: name.trim().to_string(),
+                    definition: definition.trim().to_string(),
+                    query: Query::parse(definition.trim()),
+                },
+                _ => Self {
+                    name: line.to_string(),
+                    definition: String::new(),
+                    query: Err(eyre!(
+                        "line {}: expected a name, '=' and a query",
+                        index + 1
+                    )),
+                },
+            };
+
+            playlists.push(playlist);
+        }
+
+        return playlists;
+    }
+
+    // The matching tracks of the library, in the order the library browser lists them
+    pub fn tracks(&self, library: &Library, plays: &PlayCounts) -> Result<Playlist> {
+        let query = self.query.as_ref().map_err(|error| eyre!("{}", error))?;
+
+        let mut playlist = Playlist::new();
+        playlist.entries = sorted_tracks(&library.tracks, Grouping::Artist)
+            .into_iter()
+            .map(|index| &library.tracks[index])
+            .filter(|track| {
+                query.matches(track, plays.count(&track.path, track.cue_track.as_ref()))
+            })
+            .map(|track| track.playlist_entry())
+            .collect();
+
+        return Ok(playlist);
+    }
+}
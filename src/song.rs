@@ -1,91 +1,289 @@
-use std::path::PathBuf;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use lofty::file::AudioFile;
-use lofty::file::TaggedFileExt;
-use lofty::prelude::ItemKey;
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+
+use lofty::config::ParseOptions;
+use lofty::file::{AudioFile, FileType, TaggedFile, TaggedFileExt};
+use lofty::id3::v2::{Frame, Id3v2Tag};
+use lofty::mp4::{Mp4Codec, Mp4File};
+use lofty::mpeg::MpegFile;
+use lofty::prelude::{Accessor, ItemKey};
 use lofty::probe::Probe;
+use lofty::tag::Tag;
+
+use crate::cue::CueTrack;
+
+#[derive(Clone)]
 pub struct Song {
     pub file_path: String,
 
-    pub title: String,
-    pub artist: String,
-    pub album: String,
+    // Tags, `None` where the file doesn't have them
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub composer: Option<String>,
+    pub genre: Option<String>,
+    pub comment: Option<String>,
+    // The recording date as tagged, which is often just the year
+    pub date: Option<String>,
+    pub track_number: Option<u32>,
+    pub track_total: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub disc_total: Option<u32>,
+    // Stars out of 5
+    pub rating: Option<u8>,
+
+    // Audio properties
+    pub duration: Duration,
     pub samplerate: usize,
     pub channels: usize,
+    // In kbps
+    pub bitrate: Option<u32>,
+    // Only lossless formats have one
+    pub bit_depth: Option<u8>,
+    pub codec: &'static str,
+
+    // ReplayGain values, gains in dB and peaks as linear sample amplitude
+    pub track_gain: Option<f32>,
+    pub track_peak: Option<f32>,
+    pub album_gain: Option<f32>,
+    pub album_peak: Option<f32>,
+
+    // Set when this is only a part of the file, as described by a CUE sheet
+    pub cue_track: Option<CueTrack>,
 }
 
 impl Song {
-    pub fn new(file_path: &str) -> Self {
-        //let buffered = BufReader::new(file);
-        let path = std::path::Path::new(&file_path);
-        match Probe::open(path).unwrap().read() {
-            Ok(tagged_file) => {
-                if let Some(tag) = tagged_file.primary_tag() {
-                    let title = if let Some(title) = tag.get_string(&ItemKey::TrackTitle) {
-                        title.to_string()
-                    } else {
-                        String::from("-")
-                    };
-
-                    let artist = if let Some(artist) = tag.get_string(&ItemKey::TrackArtist) {
-                        artist.to_string()
-                    } else {
-                        String::from("-")
-                    };
-
-                    let album = if let Some(album) = tag.get_string(&ItemKey::AlbumTitle) {
-                        album.to_string()
-                    } else {
-                        String::from("-")
-                    };
-
-                    let properties = tagged_file.properties();
-                    let sample_rate = properties.sample_rate().unwrap();
-                    let cc = properties.channels().unwrap();
-
-                    return Self {
-                        file_path: file_path.to_string(),
-                        //source : Decoder::try_from(buffered).unwrap(),
-                        title: title,
-                        artist: artist,
-                        album: album,
-                        samplerate: sample_rate.try_into().unwrap(),
-                        channels: cc.try_into().unwrap(),
-                    };
-                } else {
-                    return Self {
-                        file_path: file_path.to_string(),
-                        //source : Decoder::try_from(buffered).unwrap(),
-                        title: String::from("-"),
-                        artist: String::from("-"),
-                        album: String::from("-"),
-                        samplerate: 44100,
-                        channels: 2,
-                    };
-                }
-            }
-            _ => {
-                return Self {
-                    file_path: file_path.to_string(),
-                    //source : Decoder::try_from(buffered).unwrap(),
-                    title: String::from("-"),
-                    artist: String::from("-"),
-                    album: String::from("-"),
-                    samplerate: 44100,
-                    channels: 2,
-                };
+    // Fails for files that can't be opened, whose format lofty doesn't know,
+    // or that lack the audio properties needed for playback
+    pub fn new(file_path: &str) -> Result<Self> {
+        let path = Path::new(&file_path);
+        // The format is taken from the contents, a wrong extension would throw lofty off
+        let probe = Probe::open(path)?.guess_file_type()?;
+
+        // Lofty leaves POPM frames out of the generic tag, so MP3s are read as such to get
+        // their rating before they're turned into a `TaggedFile`
+        let (tagged_file, popularimeter_rating) = match probe.file_type() {
+            Some(FileType::Mpeg) => {
+                let mpeg_file = MpegFile::read_from(&mut probe.into_inner(), ParseOptions::new())?;
+                let rating = mpeg_file.id3v2().and_then(Self::read_popularimeter);
+
+                (TaggedFile::from(mpeg_file), rating)
             }
+            _ => (probe.read()?, None),
+        };
+
+        let properties = tagged_file.properties();
+        let sample_rate = properties
+            .sample_rate()
+            .ok_or_else(|| eyre!("unknown sample rate"))?;
+        let channels = properties
+            .channels()
+            .ok_or_else(|| eyre!("unknown channel count"))?;
+
+        let tag = tagged_file.primary_tag();
+        let text = |key: ItemKey| {
+            tag.and_then(|tag| tag.get_string(&key))
+                .map(|value| value.to_string())
+        };
+
+        return Ok(Self {
+            file_path: file_path.to_string(),
+
+            title: text(ItemKey::TrackTitle),
+            artist: text(ItemKey::TrackArtist),
+            album: text(ItemKey::AlbumTitle),
+            album_artist: text(ItemKey::AlbumArtist),
+            composer: text(ItemKey::Composer),
+            genre: tag
+                .and_then(|tag| tag.genre())
+                .map(|genre| genre.to_string()),
+            comment: tag
+                .and_then(|tag| tag.comment())
+                .map(|comment| comment.to_string()),
+            date: text(ItemKey::RecordingDate).or_else(|| text(ItemKey::Year)),
+            track_number: tag.and_then(|tag| tag.track()),
+            track_total: tag.and_then(|tag| tag.track_total()),
+            disc_number: tag.and_then(|tag| tag.disk()),
+            disc_total: tag.and_then(|tag| tag.disk_total()),
+            rating: tag.and_then(Self::read_rating).or(popularimeter_rating),
+
+            duration: properties.duration(),
+            samplerate: sample_rate as usize,
+            channels: channels.into(),
+            bitrate: properties.audio_bitrate().or(properties.overall_bitrate()),
+            bit_depth: properties.bit_depth(),
+            codec: Self::codec_name(path, &tagged_file),
+
+            track_gain: tag
+                .and_then(|tag| Self::read_replay_gain(tag, ItemKey::ReplayGainTrackGain)),
+            track_peak: tag
+                .and_then(|tag| Self::read_replay_gain(tag, ItemKey::ReplayGainTrackPeak)),
+            album_gain: tag
+                .and_then(|tag| Self::read_replay_gain(tag, ItemKey::ReplayGainAlbumGain)),
+            album_peak: tag
+                .and_then(|tag| Self::read_replay_gain(tag, ItemKey::ReplayGainAlbumPeak)),
+
+            cue_track: None,
+        });
+    }
+
+    // MP4 files can hold several codecs, which takes another look at the file to tell apart
+    fn codec_name(path: &Path, tagged_file: &TaggedFile) -> &'static str {
+        match tagged_file.file_type() {
+            FileType::Aac => return "AAC",
+            FileType::Aiff => return "AIFF",
+            FileType::Ape => return "APE",
+            FileType::Flac => return "FLAC",
+            FileType::Mpeg => return "MP3",
+            FileType::Mpc => return "Musepack",
+            FileType::Opus => return "Opus",
+            FileType::Vorbis => return "Vorbis",
+            FileType::Speex => return "Speex",
+            FileType::Wav => return "WAV",
+            FileType::WavPack => return "WavPack",
+            FileType::Mp4 => {}
+            _ => return "Unknown",
+        }
+
+        let codec = File::open(path).ok().and_then(|mut file| {
+            let options = ParseOptions::new().read_tags(false);
+            Mp4File::read_from(&mut file, options).ok()
+        });
+
+        match codec.as_ref().map(|file| file.properties().codec()) {
+            Some(Mp4Codec::AAC) => return "AAC",
+            Some(Mp4Codec::ALAC) => return "ALAC",
+            Some(Mp4Codec::MP3) => return "MP3",
+            Some(Mp4Codec::FLAC) => return "FLAC",
+            _ => return "MP4",
+        }
+    }
+
+    // Turns the song into one track of its file, going by what the CUE sheet says about it
+    pub fn set_cue_track(&mut self, track: CueTrack) -> () {
+        if track.title.is_some() {
+            self.title = track.title.clone();
+        }
+        if track.performer.is_some() {
+            self.artist = track.performer.clone();
         }
+        if track.album.is_some() {
+            self.album = track.album.clone();
+        }
+        self.track_number = Some(track.number);
+
+        self.duration = match track.end {
+            Some(end) => end.saturating_sub(track.start),
+            None => self.duration.saturating_sub(track.start),
+        };
+
+        self.cue_track = Some(track);
+    }
+
+    // Ratings out of 100, as players tend to write them into Vorbis comments and MP4 files,
+    // are scaled down to stars
+    fn read_rating(tag: &Tag) -> Option<u8> {
+        let value: f32 = tag
+            .get_string(&ItemKey::Popularimeter)?
+            .trim()
+            .parse()
+            .ok()?;
+        let stars = match value {
+            value if value <= 5.0 => value,
+            value if value <= 10.0 => value / 2.0,
+            value => value / 20.0,
+        };
+
+        return Some(stars.round().clamp(0.0, 5.0) as u8);
+    }
+
+    // MP3 files keep theirs in an ID3v2 frame instead
+    fn read_popularimeter(tag: &Id3v2Tag) -> Option<u8> {
+        return tag.into_iter().find_map(|frame| match frame {
+            Frame::Popularimeter(popularimeter) => stars_from_popularimeter(popularimeter.rating),
+            _ => None,
+        });
+    }
+
+    // Values look like "-6.54 dB" for gains and "0.988547" for peaks
+    fn read_replay_gain(tag: &Tag, key: ItemKey) -> Option<f32> {
+        let value = tag.get_string(&key)?.trim();
+        let value = value
+            .strip_suffix("dB")
+            .or_else(|| value.strip_suffix("db"))
+            .unwrap_or(value);
+
+        return value.trim().parse().ok();
     }
 
+    // Linear volume factor for this song's ReplayGain, lowered where needed so the peak doesn't clip.
+    // Falls back to the other gain type if the requested one is missing from the tags
+    pub fn replay_gain_factor(&self, album: bool) -> f32 {
+        let (gain, peak) = match album {
+            true => (
+                self.album_gain.or(self.track_gain),
+                self.album_peak.or(self.track_peak),
+            ),
+            false => (
+                self.track_gain.or(self.album_gain),
+                self.track_peak.or(self.album_peak),
+            ),
+        };
+
+        let factor = match gain {
+            Some(gain) => 10f32.powf(gain / 20.0),
+            None => return 1.0,
+        };
+
+        match peak {
+            Some(peak) if peak > 0.0 => return factor.min(1.0 / peak),
+            _ => return factor,
+        }
+    }
+
+    // Like "FLAC, 16 bit, 44.1 kHz, stereo, 1001 kbps", leaving out what isn't known
+    pub fn format_description(&self) -> String {
+        let mut parts: Vec<String> = vec![self.codec.to_string()];
+
+        if let Some(bit_depth) = self.bit_depth {
+            parts.push(format!("{} bit", bit_depth));
+        }
+        parts.push(format!("{} kHz", self.samplerate as f32 / 1000.0));
+        parts.push(match self.channels {
+            1 => String::from("mono"),
+            2 => String::from("stereo"),
+            channels => format!("{} channels", channels),
+        });
+        if let Some(bitrate) = self.bitrate {
+            parts.push(format!("{} kbps", bitrate));
+        }
+
+        return parts.join(", ");
+    }
+
+    // Untagged files go by their file name
     pub fn title_clone(&self) -> String {
-        return self.title.clone();
+        match &self.title {
+            Some(title) => return title.clone(),
+            None => {
+                return self
+                    .file_path_as_path()
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().to_string())
+                    .unwrap_or_default();
+            }
+        }
     }
     pub fn album_clone(&self) -> String {
-        return self.album.clone();
+        return self.album.clone().unwrap_or(String::from("-"));
     }
     pub fn artist_clone(&self) -> String {
-        return self.artist.clone();
+        return self.artist.clone().unwrap_or(String::from("-"));
     }
 
     pub fn file_path_clone(&self) -> String {
@@ -95,3 +293,127 @@ impl Song {
         return PathBuf::from(&self.file_path);
     }
 }
+
+// The ID3v2 rating goes from 1 to 255, in the steps Windows Media Player uses for its stars.
+// 0 means unrated
+fn stars_from_popularimeter(rating: u8) -> Option<u8> {
+    match rating {
+        0 => return None,
+        1..=63 => return Some(1),
+        64..=127 => return Some(2),
+        128..=195 => return Some(3),
+        196..=254 => return Some(4),
+        255 => return Some(5),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use lofty::tag::TagType;
+
+    use crate::testing::{test_dir, write_wav};
+
+    #[test]
+    fn popularimeter_steps() {
+        let stars: Vec<(u8, Option<u8>)> = [0, 1, 63, 64, 127, 128, 195, 196, 254, 255]
+            .into_iter()
+            .map(|rating| (rating, stars_from_popularimeter(rating)))
+            .collect();
+
+        assert_eq!(
+            stars,
+            vec![
+                (0, None),
+                (1, Some(1)),
+                (63, Some(1)),
+                (64, Some(2)),
+                (127, Some(2)),
+                (128, Some(3)),
+                (195, Some(3)),
+                (196, Some(4)),
+                (254, Some(4)),
+                (255, Some(5)),
+            ]
+        );
+    }
+
+    #[test]
+    fn ratings_scale_to_stars() {
+        let rating = |value: &str| {
+            let mut tag = Tag::new(TagType::VorbisComments);
+            tag.insert_text(ItemKey::Popularimeter, value.to_string());
+            return Song::read_rating(&tag);
+        };
+
+        assert_eq!(rating("4"), Some(4));
+        assert_eq!(rating("7"), Some(4));
+        assert_eq!(rating("60"), Some(3));
+        assert_eq!(rating(" 100 "), Some(5));
+        assert_eq!(rating("unrated"), None);
+    }
+
+    // An untagged song, to put ReplayGain values on
+    fn song(name: &str) -> Song {
+        let dir = test_dir(name);
+        let path = dir.join("a.wav");
+        write_wav(&path);
+
+        let song = Song::new(&path.to_string_lossy()).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+
+        return song;
+    }
+
+    fn assert_near(factor: f32, expected: f32) -> () {
+        assert!(
+            (factor - expected).abs() < 1e-4,
+            "{} isn't {}",
+            factor,
+            expected
+        );
+    }
+
+    #[test]
+    fn replay_gain_falls_back_to_the_other_type() {
+        let mut song = song("song-gain-fallback");
+        assert_eq!(song.replay_gain_factor(false), 1.0);
+        assert_eq!(song.replay_gain_factor(true), 1.0);
+
+        song.track_gain = Some(-6.0);
+        assert_near(song.replay_gain_factor(false), 0.501_187);
+        assert_near(song.replay_gain_factor(true), 0.501_187);
+
+        song.album_gain = Some(-20.0);
+        assert_near(song.replay_gain_factor(false), 0.501_187);
+        assert_near(song.replay_gain_factor(true), 0.1);
+
+        song.track_gain = None;
+        assert_near(song.replay_gain_factor(false), 0.1);
+    }
+
+    #[test]
+    fn replay_gain_does_not_clip_the_peak() {
+        let mut song = song("song-gain-peak");
+        song.track_gain = Some(6.0);
+        song.album_gain = Some(6.0);
+
+        // +6 dB would take a peak of 0.8 past full scale, so it's held at 1 / 0.8
+        song.track_peak = Some(0.8);
+        assert_near(song.replay_gain_factor(false), 1.25);
+        // The album peak falls back to the track's as well
+        assert_near(song.replay_gain_factor(true), 1.25);
+
+        song.album_peak = Some(0.25);
+        assert_near(song.replay_gain_factor(true), 1.995_262);
+
+        // Peaks that can't be right are ignored
+        song.track_peak = Some(0.0);
+        assert_near(song.replay_gain_factor(false), 1.995_262);
+        song.track_peak = Some(-1.0);
+        assert_near(song.replay_gain_factor(false), 1.995_262);
+    }
+}
@@ -0,0 +1,420 @@
+use std::f32::consts::PI;
+use std::time::{Duration, Instant};
+
+use rustfft::{FftPlanner, num_complex::Complex};
+
+// Lowest frequency that gets a band, below it there is hardly anything audible
+const MIN_FREQUENCY: f32 = 20.0;
+
+// Floors cycled through by `cycle_floor`, anything quieter shows as an empty band
+const FLOOR_STEPS: [f32; 3] = [-40.0, -60.0, -80.0];
+
+// How quickly bands rise to louder levels and fall back from them, as time constants
+const ATTACK: Duration = Duration::from_millis(25);
+const DECAY: Duration = Duration::from_millis(250);
+
+// Peak markers stay put for a while before they fall, at this many band heights per second
+const PEAK_HOLD: Duration = Duration::from_millis(600);
+const PEAK_FALL_SPEED: f32 = 0.8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Window {
+    Hann,
+    // Leaks less into neighbouring bands than Hann, at the cost of wider peaks
+    Blackman,
+}
+
+impl Window {
+    fn coefficients(self, len: usize) -> Vec<f32> {
+        let denominator = len.saturating_sub(1).max(1) as f32;
+
+        return (0..len)
+            .map(|n| {
+                let phase = 2.0 * PI * n as f32 / denominator;
+                match self {
+                    Window::Hann => 0.5 - 0.5 * phase.cos(),
+                    Window::Blackman => 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos(),
+                }
+            })
+            .collect();
+    }
+}
+
+// One frame of the visualizer, both levels and peaks going from 0 (the floor) to 1 (0 dB)
+pub struct SpectrumFrame {
+    pub levels: Vec<f32>,
+    pub peaks: Vec<f32>,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Peak {
+    pub level: f32,
+    // Time since the peak was last pushed up
+    age: Duration,
+}
+
+// Turns the latest played samples into band levels, carrying the smoothing and the peaks over
+// from one frame to the next
+pub struct Spectrum {
+    planner: FftPlanner<f32>,
+
+    window: Window,
+    floor_db: f32,
+
+    levels: Vec<f32>,
+    peaks: Vec<Peak>,
+    last_frame: Option<Instant>,
+}
+
+impl Spectrum {
+    pub fn new() -> Self {
+        Self {
+            planner: FftPlanner::new(),
+
+            window: Window::Hann,
+            floor_db: FLOOR_STEPS[1],
+
+            levels: Vec::new(),
+            peaks: Vec::new(),
+            last_frame: None,
+        }
+    }
+
+    // `samples` are interleaved, all channels get summed into one
+    pub fn analyse(
+        &mut self,
+        samples: &[f32],
+        channels: u16,
+        sample_rate: u32,
+        n_bands: usize,
+    ) -> SpectrumFrame {
+        let now = Instant::now();
+        let elapsed = self
+            .last_frame
+            .map_or(Duration::ZERO, |last| now.duration_since(last));
+        self.last_frame = Some(now);
+
+        let mono = mix_to_mono(samples, channels);
+        let magnitudes = magnitudes(&mut self.planner, &mono, self.window);
+        let targets: Vec<f32> =
+            split_into_bands(&magnitudes, sample_rate as f32, mono.len(), n_bands)
+                .into_iter()
+                .map(|amplitude| to_level(amplitude, self.floor_db))
+                .collect();
+
+        // A resized terminal changes the number of bands, which starts them over
+        if self.levels.len() != n_bands {
+            self.levels = targets.clone();
+            self.peaks = vec![Peak::default(); n_bands];
+        }
+
+        for ((level, peak), target) in self.levels.iter_mut().zip(&mut self.peaks).zip(targets) {
+            *level = smooth(*level, target, elapsed);
+            *peak = update_peak(*peak, *level, elapsed);
+        }
+
+        return SpectrumFrame {
+            levels: self.levels.clone(),
+            peaks: self.peaks.iter().map(|peak| peak.level).collect(),
+        };
+    }
+
+    pub fn cycle_floor(&mut self) -> () {
+        let step = FLOOR_STEPS
+            .iter()
+            .position(|step| *step == self.floor_db)
+            .unwrap_or(0);
+
+        self.floor_db = FLOOR_STEPS[(step + 1) % FLOOR_STEPS.len()];
+    }
+
+    pub fn floor_db(&self) -> f32 {
+        return self.floor_db;
+    }
+
+    pub fn cycle_window(&mut self) -> () {
+        self.window = match self.window {
+            Window::Hann => Window::Blackman,
+            Window::Blackman => Window::Hann,
+        };
+    }
+
+    pub fn window(&self) -> Window {
+        return self.window;
+    }
+}
+
+// Averages the channels of each frame, so that mono and stereo look the same
+pub fn mix_to_mono(samples: &[f32], channels: u16) -> Vec<f32> {
+    let channels = channels.max(1) as usize;
+
+    return samples
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+}
+
+// The amplitude in each FFT bin up to the Nyquist frequency. It's scaled so that a sine wave
+// which lands on a bin shows its own amplitude there, whatever the window and buffer size
+fn magnitudes(planner: &mut FftPlanner<f32>, samples: &[f32], window: Window) -> Vec<f32> {
+    if samples.is_empty() {
+        return Vec::new();
+    }
+
+    let coefficients = window.coefficients(samples.len());
+    let gain: f32 = coefficients.iter().sum();
+
+    let mut buffer: Vec<Complex<f32>> = samples
+        .iter()
+        .zip(&coefficients)
+        .map(|(sample, coefficient)| Complex {
+            re: sample * coefficient,
+            im: 0.0,
+        })
+        .collect();
+
+    planner.plan_fft_forward(buffer.len()).process(&mut buffer);
+
+    return buffer[..samples.len() / 2]
+        .iter()
+        .map(|bin| 2.0 * bin.norm() / gain)
+        .collect();
+}
+
+// Groups the bins into logarithmically spaced bands from 20Hz up to the Nyquist frequency,
+// each taking its loudest bin. Low bands can be narrower than a bin, those take the bin
+// closest to their centre
+fn split_into_bands(
+    magnitudes: &[f32],
+    sample_rate: f32,
+    fft_size: usize,
+    n_bands: usize,
+) -> Vec<f32> {
+    if magnitudes.is_empty() || fft_size == 0 {
+        return vec![0.0; n_bands];
+    }
+
+    let freq_per_bin = sample_rate / fft_size as f32;
+    let nyquist = sample_rate / 2.0;
+
+    let log_min = MIN_FREQUENCY.ln();
+    let log_max = nyquist.ln();
+    let edge = |i: usize| (log_min + (log_max - log_min) * i as f32 / n_bands as f32).exp();
+
+    return (0..n_bands)
+        .map(|band| {
+            let (low, high) = (edge(band), edge(band + 1));
+
+            let first = (low / freq_per_bin).ceil() as usize;
+            let last = ((high / freq_per_bin).ceil() as usize).min(magnitudes.len());
+
+            match first < last {
+                true => return magnitudes[first..last].iter().copied().fold(0.0, f32::max),
+                false => {
+                    let centre = ((low * high).sqrt() / freq_per_bin).round() as usize;
+                    return magnitudes
+                        .get(centre.min(magnitudes.len() - 1))
+                        .copied()
+                        .unwrap_or(0.0);
+                }
+            }
+        })
+        .collect();
+}
+
+// Maps an amplitude onto 0 at the floor to 1 at full scale
+pub fn to_level(amplitude: f32, floor_db: f32) -> f32 {
+    if amplitude <= 0.0 {
+        return 0.0;
+    }
+
+    let db = 20.0 * amplitude.log10();
+    return ((db - floor_db) / -floor_db).clamp(0.0, 1.0);
+}
+
+// Moves a band towards its new level, quicker on the way up than on the way down
+pub fn smooth(previous: f32, target: f32, elapsed: Duration) -> f32 {
+    let time_constant = match target > previous {
+        true => ATTACK,
+        false => DECAY,
+    };
+
+    let factor = 1.0 - (-elapsed.as_secs_f32() / time_constant.as_secs_f32()).exp();
+    return previous + (target - previous) * factor;
+}
+
+pub fn update_peak(peak: Peak, level: f32, elapsed: Duration) -> Peak {
+    let age = peak.age + elapsed;
+
+    // Only the time past the hold counts towards the fall
+    let falling_for = age.saturating_sub(PEAK_HOLD).min(elapsed);
+    let fallen = peak.level - PEAK_FALL_SPEED * falling_for.as_secs_f32();
+
+    match level >= fallen {
+        true => {
+            return Peak {
+                level,
+                age: Duration::ZERO,
+            };
+        }
+        false => return Peak { level: fallen, age },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    fn sine(frequency: f32, amplitude: f32, len: usize) -> Vec<f32> {
+        return (0..len)
+            .map(|n| amplitude * (2.0 * PI * frequency * n as f32 / SAMPLE_RATE as f32).sin())
+            .collect();
+    }
+
+    fn bands_of(samples: &[f32], window: Window, n_bands: usize) -> Vec<f32> {
+        let magnitudes = magnitudes(&mut FftPlanner::new(), samples, window);
+        return split_into_bands(&magnitudes, SAMPLE_RATE as f32, samples.len(), n_bands);
+    }
+
+    fn loudest(bands: &[f32]) -> usize {
+        return bands
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .unwrap()
+            .0;
+    }
+
+    // The frequency range of a band, as `split_into_bands` lays them out
+    fn band_range(band: usize, n_bands: usize) -> (f32, f32) {
+        let log_min = MIN_FREQUENCY.ln();
+        let log_max = (SAMPLE_RATE as f32 / 2.0).ln();
+        let edge = |i: usize| (log_min + (log_max - log_min) * i as f32 / n_bands as f32).exp();
+
+        return (edge(band), edge(band + 1));
+    }
+
+    #[test]
+    fn sine_lands_in_its_band() {
+        for frequency in [100.0, 1000.0, 5000.0, 15000.0] {
+            for fft_size in [1024, 2048, 4096] {
+                let bands = bands_of(&sine(frequency, 1.0, fft_size), Window::Hann, 32);
+                let (low, high) = band_range(loudest(&bands), 32);
+
+                // The peak can spill over an edge by a bin
+                let freq_per_bin = SAMPLE_RATE as f32 / fft_size as f32;
+                assert!(
+                    low - freq_per_bin <= frequency && frequency <= high + freq_per_bin,
+                    "{}Hz with {} samples ended up in {}-{}Hz",
+                    frequency,
+                    fft_size,
+                    low,
+                    high
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn full_scale_sine_reads_as_full_scale() {
+        // Exactly on bin 43 of a 2048 point FFT
+        let frequency = 43.0 * SAMPLE_RATE as f32 / 2048.0;
+
+        for window in [Window::Hann, Window::Blackman] {
+            let bands = bands_of(&sine(frequency, 1.0, 2048), window, 32);
+            let peak = bands[loudest(&bands)];
+
+            assert!((peak - 1.0).abs() < 0.01, "{:?} gave {}", window, peak);
+            assert!((to_level(peak, -60.0) - 1.0).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn quiet_sines_stay_quiet() {
+        let frequency = 43.0 * SAMPLE_RATE as f32 / 2048.0;
+
+        let loud = bands_of(&sine(frequency, 1.0, 2048), Window::Hann, 32);
+        let quiet = bands_of(&sine(frequency, 0.01, 2048), Window::Hann, 32);
+        let band = loudest(&loud);
+
+        // A hundredth of the amplitude is 40dB down, which is a third of the way up from -60dB
+        let level = to_level(quiet[band], -60.0);
+        assert!((level - 1.0 / 3.0).abs() < 0.01, "got {}", level);
+        assert!(to_level(quiet[band], -40.0) < 0.01);
+    }
+
+    #[test]
+    fn window_keeps_far_bands_down() {
+        // Halfway between two bins, where leakage is at its worst
+        let frequency = 43.5 * SAMPLE_RATE as f32 / 2048.0;
+
+        for window in [Window::Hann, Window::Blackman] {
+            let bands = bands_of(&sine(frequency, 1.0, 2048), window, 32);
+            let band = loudest(&bands);
+
+            for far in bands.iter().skip(band + 4) {
+                assert!(to_level(*far, -60.0) < 0.05, "{:?} leaked {}", window, far);
+            }
+        }
+    }
+
+    #[test]
+    fn silence_is_empty() {
+        let bands = bands_of(&[0.0; 2048], Window::Hann, 16);
+
+        assert!(bands.iter().all(|band| to_level(*band, -80.0) == 0.0));
+        assert_eq!(split_into_bands(&[], 48000.0, 0, 16), vec![0.0; 16]);
+    }
+
+    #[test]
+    fn stereo_gets_summed() {
+        let left = sine(1000.0, 1.0, 1024);
+        let interleaved: Vec<f32> = left.iter().flat_map(|sample| [*sample, 0.0]).collect();
+
+        let mono = mix_to_mono(&interleaved, 2);
+        assert_eq!(mono.len(), 1024);
+        assert!(
+            mono.iter()
+                .zip(&left)
+                .all(|(m, l)| (m - l / 2.0).abs() < 1e-6)
+        );
+
+        // Opposite channels cancel out
+        let cancelling: Vec<f32> = left.iter().flat_map(|sample| [*sample, -sample]).collect();
+        assert!(mix_to_mono(&cancelling, 2).iter().all(|m| *m == 0.0));
+    }
+
+    #[test]
+    fn bands_rise_faster_than_they_fall() {
+        let frame = Duration::from_millis(50);
+
+        let risen = smooth(0.0, 1.0, frame);
+        let fallen = smooth(1.0, 0.0, frame);
+
+        assert!(risen > 0.8);
+        assert!(fallen > 0.7);
+        assert_eq!(smooth(0.3, 0.9, Duration::ZERO), 0.3);
+    }
+
+    #[test]
+    fn peaks_hold_then_fall() {
+        let peak = update_peak(Peak::default(), 0.9, Duration::ZERO);
+        assert_eq!(peak.level, 0.9);
+
+        // Held while the band drops away
+        let peak = update_peak(peak, 0.1, Duration::from_millis(500));
+        assert_eq!(peak.level, 0.9);
+
+        // Past the hold only the part after it counts: 0.2s at 0.8 per second
+        let peak = update_peak(peak, 0.1, Duration::from_millis(300));
+        assert!((peak.level - 0.74).abs() < 1e-4, "got {}", peak.level);
+
+        // Never below the band itself, and pushed up again by it
+        let peak = update_peak(peak, 0.1, Duration::from_secs(5));
+        assert_eq!(peak.level, 0.1);
+        let peak = update_peak(peak, 0.5, Duration::from_millis(10));
+        assert_eq!((peak.level, peak.age), (0.5, Duration::ZERO));
+    }
+}
@@ -0,0 +1,240 @@
+use std::fs;
+use std::path::PathBuf;
+
+use color_eyre::Result;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cue::CueTrack;
+use crate::player::RepeatMode;
+use crate::shuffle::PlayOrder;
+use crate::song::Song;
+use crate::xdg_dir;
+
+// Bumped whenever the layout of `SavedState` changes, older files are then ignored
+const STATE_VERSION: u32 = 2;
+
+const STATE_FILE_NAME: &str = "state.json";
+
+// Everything about the player that is restored on the next start
+#[derive(Serialize, Deserialize)]
+pub struct SavedState {
+    pub version: u32,
+
+    // The queue entries, in the order they were added
+    pub queue: Vec<SavedEntry>,
+    // Play order and pre-shuffle order, as indices into `queue`
+    pub play_order: Vec<usize>,
+    pub original_order: Vec<usize>,
+    pub shuffled: bool,
+
+    pub player_index: usize,
+    pub position_secs: f64,
+
+    pub volume: f32,
+    pub repeat_mode: RepeatMode,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SavedEntry {
+    pub path: String,
+    // Entries from CUE sheets only play a part of the file
+    pub cue_track: Option<CueTrack>,
+}
+
+impl SavedState {
+    pub fn new() -> Self {
+        Self {
+            version: STATE_VERSION,
+
+            queue: Vec::new(),
+            play_order: Vec::new(),
+            original_order: Vec::new(),
+            shuffled: false,
+
+            player_index: 0,
+            position_secs: 0.0,
+
+            volume: 1.0,
+            repeat_mode: RepeatMode::Off,
+        }
+    }
+
+    fn file_path() -> PathBuf {
+        return xdg_dir("XDG_STATE_HOME", "~/.local/state").join(STATE_FILE_NAME);
+    }
+
+    // Missing, unreadable or outdated state files all mean starting from scratch
+    pub fn load() -> Option<Self> {
+        let contents = fs::read_to_string(Self::file_path()).ok()?;
+
+        return Self::parse(&contents);
+    }
+
+    fn parse(contents: &str) -> Option<Self> {
+        let state: SavedState = serde_json::from_str(contents).ok()?;
+
+        if state.version != STATE_VERSION {
+            return None;
+        }
+
+        return Some(state);
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::file_path();
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+
+        Ok(())
+    }
+}
+
+// Reads the songs of the saved queue back, taking those that can't be read anymore out of
+// the play order. Gives the songs along with the fixed up `player_index`, and whether the
+// track that was playing is among the ones that are gone
+pub fn restore_queue(
+    queue: Vec<SavedEntry>,
+    play_order: &mut PlayOrder,
+    player_index: usize,
+) -> (Vec<Song>, usize, bool) {
+    let len = queue.len();
+    let songs: Vec<Option<Song>> = queue
+        .into_iter()
+        .map(|entry| {
+            let mut song = Song::new(&entry.path).ok()?;
+            if let Some(track) = entry.cue_track {
+                song.set_cue_track(track);
+            }
+            Some(song)
+        })
+        .collect();
+
+    let mut player_index = player_index.min(len);
+    let mut current_removed = false;
+
+    // Going backwards keeps the queue indices of the entries still to check intact
+    for queue_index in (0..len).rev() {
+        if songs[queue_index].is_some() {
+            continue;
+        }
+
+        let position = play_order.position_of(queue_index).unwrap();
+        play_order.remove(position);
+
+        if position + 1 < player_index {
+            player_index -= 1;
+        } else if position + 1 == player_index {
+            player_index -= 1;
+            current_removed = true;
+        }
+    }
+
+    return (
+        songs.into_iter().flatten().collect(),
+        player_index,
+        current_removed,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::{test_dir, write_wav};
+
+    #[test]
+    fn outdated_state_files_are_ignored() {
+        let mut state = SavedState::new();
+        assert!(SavedState::parse(&serde_json::to_string(&state).unwrap()).is_some());
+
+        state.version = STATE_VERSION - 1;
+        assert!(SavedState::parse(&serde_json::to_string(&state).unwrap()).is_none());
+
+        // Older layouts may not even parse
+        assert!(SavedState::parse("{\"version\": 1, \"queue\": []}").is_none());
+        assert!(SavedState::parse("").is_none());
+    }
+
+    // A queue of the files `a.wav` to `e.wav`, of which only the ones in `present` exist,
+    // restored into `order`
+    fn restore(
+        name: &str,
+        present: &str,
+        order: Vec<usize>,
+        player_index: usize,
+    ) -> (Vec<String>, Vec<usize>, usize, bool) {
+        let dir = test_dir(name);
+        let names = ["a", "b", "c", "d", "e"];
+
+        let queue: Vec<SavedEntry> = names
+            .iter()
+            .map(|name| {
+                let path = dir.join(format!("{}.wav", name));
+                if present.contains(name) {
+                    write_wav(&path);
+                }
+                SavedEntry {
+                    path: path.to_string_lossy().to_string(),
+                    cue_track: None,
+                }
+            })
+            .collect();
+
+        let mut play_order = PlayOrder::new(None);
+        assert!(play_order.restore(order, (0..5).collect(), true, 5));
+
+        let (songs, player_index, current_removed) =
+            restore_queue(queue, &mut play_order, player_index);
+        let titles = songs.iter().map(|song| song.title_clone()).collect();
+
+        fs::remove_dir_all(dir).unwrap();
+
+        return (
+            titles,
+            play_order.as_slice().to_vec(),
+            player_index,
+            current_removed,
+        );
+    }
+
+    #[test]
+    fn missing_files_are_dropped_from_the_queue() {
+        let (titles, order, player_index, current_removed) =
+            restore("state-missing", "ace", vec![0, 1, 2, 3, 4], 1);
+
+        assert_eq!(titles, vec!["a", "c", "e"]);
+        assert_eq!(order, vec![0, 1, 2]);
+        assert_eq!(player_index, 1);
+        assert!(!current_removed);
+    }
+
+    #[test]
+    fn playback_stays_on_its_track_when_an_earlier_one_is_gone() {
+        // `d` is playing, `b` was played before it
+        let (titles, order, player_index, current_removed) =
+            restore("state-earlier", "acde", vec![4, 1, 0, 3, 2], 4);
+
+        assert_eq!(titles, vec!["a", "c", "d", "e"]);
+        assert_eq!(order, vec![3, 0, 2, 1]);
+        assert_eq!(player_index, 3);
+        assert_eq!(titles[order[player_index - 1]], "d");
+        assert!(!current_removed);
+    }
+
+    #[test]
+    fn playback_moves_on_when_its_track_is_gone() {
+        let (titles, order, player_index, current_removed) =
+            restore("state-current", "ace", vec![4, 1, 0, 3, 2], 4);
+
+        assert_eq!(titles, vec!["a", "c", "e"]);
+        assert_eq!(order, vec![2, 0, 1]);
+        // The track that came after `d` starts instead
+        assert_eq!(player_index, 2);
+        assert_eq!(titles[order[player_index]], "c");
+        assert!(current_removed);
+    }
+}
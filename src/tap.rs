@@ -0,0 +1,152 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use rodio::Source;
+use rodio::source::SeekError;
+
+// How many of the most recent samples are kept, about 170ms of 48kHz stereo
+const RING_LEN: usize = 1 << 14;
+
+// The most recently played samples, shared between the audio thread that writes them
+// and the visualizer that reads them. Neither side ever waits on a lock, a reader that gets
+// lapped by the writer just sees a few newer samples, which a visualizer doesn't mind
+pub struct SampleRing {
+    // The bits of each f32, as there are no atomic floats
+    samples: Box<[AtomicU32]>,
+    // Samples written so far, the next one goes to `written % RING_LEN`
+    written: AtomicUsize,
+
+    channels: u16,
+    sample_rate: u32,
+}
+
+impl SampleRing {
+    pub fn new(channels: u16, sample_rate: u32) -> Arc<Self> {
+        return Arc::new(Self {
+            samples: (0..RING_LEN).map(|_| AtomicU32::new(0)).collect(),
+            written: AtomicUsize::new(0),
+
+            channels,
+            sample_rate,
+        });
+    }
+
+    fn push(&self, sample: f32) -> () {
+        let written = self.written.load(Ordering::Relaxed);
+        self.samples[written % RING_LEN].store(sample.to_bits(), Ordering::Relaxed);
+        self.written.store(written + 1, Ordering::Release);
+    }
+
+    // Up to `count` of the latest samples, oldest first and interleaved like they were played.
+    // Always whole frames, so every `channels`th sample is from the same channel
+    pub fn latest(&self, count: usize) -> Vec<f32> {
+        let channels = self.channels.max(1) as usize;
+
+        let written = self.written.load(Ordering::Acquire);
+        let end = written - written % channels;
+        let count = count.min(end).min(RING_LEN - channels);
+        let count = count - count % channels;
+
+        return (end - count..end)
+            .map(|i| f32::from_bits(self.samples[i % RING_LEN].load(Ordering::Relaxed)))
+            .collect();
+    }
+
+    pub fn channels(&self) -> u16 {
+        return self.channels;
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        return self.sample_rate;
+    }
+}
+
+// Passes a source through unchanged while copying its samples into a ring
+pub struct Tap<S> {
+    inner: S,
+    ring: Arc<SampleRing>,
+}
+
+impl<S: Source> Tap<S> {
+    pub fn new(inner: S, ring: Arc<SampleRing>) -> Self {
+        return Self { inner, ring };
+    }
+}
+
+impl<S: Source> Iterator for Tap<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.inner.next()?;
+        self.ring.push(sample);
+
+        return Some(sample);
+    }
+}
+
+impl<S: Source> Source for Tap<S> {
+    fn current_span_len(&self) -> Option<usize> {
+        return self.inner.current_span_len();
+    }
+
+    fn channels(&self) -> u16 {
+        return self.inner.channels();
+    }
+
+    fn sample_rate(&self) -> u32 {
+        return self.inner.sample_rate();
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        return self.inner.total_duration();
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        return self.inner.try_seek(pos);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A stereo ring where every sample is its own index
+    fn ring(written: usize) -> Arc<SampleRing> {
+        let ring = SampleRing::new(2, 48000);
+        for i in 0..written {
+            ring.push(i as f32);
+        }
+
+        return ring;
+    }
+
+    fn numbers(from: usize, to: usize) -> Vec<f32> {
+        return (from..to).map(|i| i as f32).collect();
+    }
+
+    #[test]
+    fn only_whole_frames_are_read() {
+        // The last sample is the left half of a frame that isn't done yet
+        let ring = ring(7);
+
+        assert_eq!(ring.latest(4), numbers(2, 6));
+        assert_eq!(ring.latest(100), numbers(0, 6));
+        // An odd count is rounded down to whole frames
+        assert_eq!(ring.latest(3), numbers(4, 6));
+    }
+
+    #[test]
+    fn the_latest_samples_survive_wrapping_around() {
+        let written = 2 * RING_LEN + 5;
+        let ring = ring(written);
+        let end = written - 1;
+
+        assert_eq!(ring.latest(6), numbers(end - 6, end));
+
+        // At most the whole ring but one frame, oldest first and starting on the left channel
+        let all = ring.latest(usize::MAX);
+        assert_eq!(all, numbers(end - (RING_LEN - 2), end));
+        assert_eq!(all[0] as usize % 2, 0);
+    }
+}
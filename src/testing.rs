@@ -0,0 +1,38 @@
+// Files on disk for the tests of the modules that read them
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+// An empty directory of its own for every test, `name` has to be unique across all tests
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("gigr-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    return dir;
+}
+
+// A second of silence, as 8 kHz 16 bit mono
+pub fn write_wav(path: &Path) -> () {
+    let data_len: u32 = 8000 * 2;
+
+    let mut bytes = Vec::new();
+    bytes.extend(b"RIFF");
+    bytes.extend((36 + data_len).to_le_bytes());
+    bytes.extend(b"WAVEfmt ");
+    bytes.extend(16u32.to_le_bytes());
+    bytes.extend(1u16.to_le_bytes());
+    bytes.extend(1u16.to_le_bytes());
+    bytes.extend(8000u32.to_le_bytes());
+    bytes.extend(16000u32.to_le_bytes());
+    bytes.extend(2u16.to_le_bytes());
+    bytes.extend(16u16.to_le_bytes());
+    bytes.extend(b"data");
+    bytes.extend(data_len.to_le_bytes());
+    bytes.resize(bytes.len() + data_len as usize, 0);
+
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, bytes).unwrap();
+}
@@ -0,0 +1,310 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::spectrum::{Peak, mix_to_mono, smooth, to_level, update_peak};
+
+// Meters show anything quieter than this as empty
+const METER_FLOOR_DB: f32 = -60.0;
+
+// What the visualizer panel of the Track Info mode shows
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VisualizerMode {
+    Bars,
+    Oscilloscope,
+    Spectrogram,
+    Meters,
+}
+
+impl VisualizerMode {
+    pub fn next(self) -> Self {
+        match self {
+            VisualizerMode::Bars => return VisualizerMode::Oscilloscope,
+            VisualizerMode::Oscilloscope => return VisualizerMode::Spectrogram,
+            VisualizerMode::Spectrogram => return VisualizerMode::Meters,
+            VisualizerMode::Meters => return VisualizerMode::Bars,
+        }
+    }
+}
+
+// The waveform as `points` values between -1 and 1, channels summed. It starts on a rising
+// zero crossing where there is one, so that steady tones stand still instead of jittering
+pub fn oscilloscope(samples: &[f32], channels: u16, points: usize) -> Vec<f64> {
+    let mono = mix_to_mono(samples, channels);
+    if mono.is_empty() || points == 0 {
+        return Vec::new();
+    }
+
+    // Only the first half is searched, so the second half is always there to be shown
+    let visible = mono.len() / 2;
+    let start = (1..=visible)
+        .find(|i| mono[i - 1] <= 0.0 && mono[*i] > 0.0)
+        .unwrap_or(0);
+
+    return (0..points)
+        .map(|point| {
+            let sample = mono[start + point * visible.max(1) / points];
+            return (sample as f64).clamp(-1.0, 1.0);
+        })
+        .collect();
+}
+
+// The band levels of the last few frames, oldest first, for a scrolling spectrogram
+pub struct Spectrogram {
+    columns: VecDeque<Vec<f32>>,
+}
+
+impl Spectrogram {
+    pub fn new() -> Self {
+        Self {
+            columns: VecDeque::new(),
+        }
+    }
+
+    // Adds the newest column, dropping the oldest ones past `capacity`.
+    // A different number of bands means the panel was resized, which starts the history over
+    pub fn push(&mut self, column: Vec<f32>, capacity: usize) -> () {
+        if self
+            .columns
+            .back()
+            .is_some_and(|last| last.len() != column.len())
+        {
+            self.columns.clear();
+        }
+
+        self.columns.push_back(column);
+        while self.columns.len() > capacity {
+            self.columns.pop_front();
+        }
+    }
+
+    pub fn columns(&self) -> &VecDeque<Vec<f32>> {
+        return &self.columns;
+    }
+}
+
+// Colours a level between 0 and 1, going from black through blue, red and yellow to white
+pub fn heat_color(level: f32) -> (u8, u8, u8) {
+    const STOPS: [(f32, f32, f32); 5] = [
+        (0.0, 0.0, 0.0),
+        (0.0, 0.0, 180.0),
+        (200.0, 0.0, 60.0),
+        (255.0, 210.0, 0.0),
+        (255.0, 255.0, 255.0),
+    ];
+
+    let position = level.clamp(0.0, 1.0) * (STOPS.len() - 1) as f32;
+    let index = (position.floor() as usize).min(STOPS.len() - 2);
+    let t = position - index as f32;
+
+    let (from, to) = (STOPS[index], STOPS[index + 1]);
+    let mix = |a: f32, b: f32| (a + (b - a) * t).round() as u8;
+
+    return (mix(from.0, to.0), mix(from.1, to.1), mix(from.2, to.2));
+}
+
+// The loudness of one channel over the latest samples, as linear amplitudes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChannelLevel {
+    pub rms: f32,
+    pub peak: f32,
+}
+
+pub fn channel_levels(samples: &[f32], channels: u16) -> Vec<ChannelLevel> {
+    let channels = channels.max(1) as usize;
+    let frames = samples.len() / channels;
+
+    return (0..channels)
+        .map(|channel| {
+            let channel_samples = samples.iter().skip(channel).step_by(channels).take(frames);
+
+            let (sum_of_squares, peak) = channel_samples.fold((0.0, 0.0), |(sum, peak), sample| {
+                (sum + sample * sample, f32::max(peak, sample.abs()))
+            });
+
+            let rms = match frames {
+                0 => 0.0,
+                frames => (sum_of_squares / frames as f32).sqrt(),
+            };
+
+            return ChannelLevel { rms, peak };
+        })
+        .collect();
+}
+
+// Negative infinity for silence
+pub fn to_db(amplitude: f32) -> f32 {
+    return 20.0 * amplitude.log10();
+}
+
+// What a meter shows for one channel. The readings are for the latest samples, the bar
+// levels (0 at the floor, 1 at full scale) are smoothed and the peak is held for a while
+pub struct MeterReading {
+    pub rms_db: f32,
+    pub peak_db: f32,
+    pub level: f32,
+    pub peak_level: f32,
+}
+
+pub struct Meters {
+    levels: Vec<f32>,
+    peaks: Vec<Peak>,
+    last_frame: Option<Instant>,
+}
+
+impl Meters {
+    pub fn new() -> Self {
+        Self {
+            levels: Vec::new(),
+            peaks: Vec::new(),
+            last_frame: None,
+        }
+    }
+
+    pub fn update(&mut self, samples: &[f32], channels: u16) -> Vec<MeterReading> {
+        let now = Instant::now();
+        let elapsed = self
+            .last_frame
+            .map_or(Duration::ZERO, |last| now.duration_since(last));
+        self.last_frame = Some(now);
+
+        let channel_levels = channel_levels(samples, channels);
+
+        if self.levels.len() != channel_levels.len() {
+            self.levels = vec![0.0; channel_levels.len()];
+            self.peaks = vec![Peak::default(); channel_levels.len()];
+        }
+
+        return channel_levels
+            .iter()
+            .zip(self.levels.iter_mut().zip(&mut self.peaks))
+            .map(|(channel, (level, peak))| {
+                *level = smooth(*level, to_level(channel.rms, METER_FLOOR_DB), elapsed);
+                *peak = update_peak(*peak, to_level(channel.peak, METER_FLOOR_DB), elapsed);
+
+                return MeterReading {
+                    rms_db: to_db(channel.rms),
+                    peak_db: to_db(channel.peak),
+                    level: *level,
+                    peak_level: peak.level,
+                };
+            })
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::*;
+
+    fn sine(frequency: f32, amplitude: f32, len: usize) -> Vec<f32> {
+        return (0..len)
+            .map(|n| amplitude * (2.0 * PI * frequency * n as f32 / 48000.0).sin())
+            .collect();
+    }
+
+    #[test]
+    fn modes_cycle_through_all_four() {
+        let mut mode = VisualizerMode::Bars;
+        let mut seen = Vec::new();
+
+        for _ in 0..4 {
+            seen.push(mode);
+            mode = mode.next();
+        }
+
+        assert_eq!(mode, VisualizerMode::Bars);
+        assert!(seen.contains(&VisualizerMode::Oscilloscope));
+        assert!(seen.contains(&VisualizerMode::Spectrogram));
+        assert!(seen.contains(&VisualizerMode::Meters));
+    }
+
+    #[test]
+    fn oscilloscope_starts_on_a_rising_edge() {
+        // Starts a quarter of a cycle in, at the top of the wave
+        let samples: Vec<f32> = sine(100.0, 0.5, 2048 + 120).split_off(120);
+        let points = oscilloscope(&samples, 1, 64);
+
+        assert_eq!(points.len(), 64);
+        assert!(points[0].abs() < 0.01, "started at {}", points[0]);
+        assert!(points[1] > points[0]);
+        assert!(points.iter().all(|point| point.abs() <= 0.5));
+    }
+
+    #[test]
+    fn oscilloscope_sums_channels_and_clips() {
+        let stereo: Vec<f32> = (0..512).flat_map(|_| [3.0, 1.0]).collect();
+        let points = oscilloscope(&stereo, 2, 16);
+
+        assert!(points.iter().all(|point| *point == 1.0));
+        assert!(oscilloscope(&[], 2, 16).is_empty());
+        assert!(oscilloscope(&stereo, 2, 0).is_empty());
+    }
+
+    #[test]
+    fn spectrogram_scrolls_and_restarts_on_resize() {
+        let mut spectrogram = Spectrogram::new();
+
+        for i in 0..5 {
+            spectrogram.push(vec![i as f32; 3], 3);
+        }
+        let oldest: Vec<f32> = spectrogram
+            .columns()
+            .iter()
+            .map(|column| column[0])
+            .collect();
+        assert_eq!(oldest, vec![2.0, 3.0, 4.0]);
+
+        spectrogram.push(vec![0.0; 4], 3);
+        assert_eq!(spectrogram.columns().len(), 1);
+    }
+
+    #[test]
+    fn heat_gets_brighter() {
+        assert_eq!(heat_color(0.0), (0, 0, 0));
+        assert_eq!(heat_color(1.0), (255, 255, 255));
+        assert_eq!(heat_color(-1.0), heat_color(0.0));
+
+        let brightness = |level: f32| {
+            let (r, g, b) = heat_color(level);
+            r as u32 + g as u32 + b as u32
+        };
+        for step in 0..10 {
+            let level = step as f32 / 10.0;
+            assert!(brightness(level + 0.1) > brightness(level), "at {}", level);
+        }
+    }
+
+    #[test]
+    fn meters_read_rms_and_peak_per_channel() {
+        // Full scale on the left, a constant half on the right
+        let left = sine(1000.0, 1.0, 4800);
+        let stereo: Vec<f32> = left.iter().flat_map(|sample| [*sample, 0.5]).collect();
+
+        let levels = channel_levels(&stereo, 2);
+        assert_eq!(levels.len(), 2);
+
+        // A sine's RMS is its peak over the square root of two, 3dB down
+        assert!((levels[0].peak - 1.0).abs() < 1e-3);
+        assert!((to_db(levels[0].rms) + 3.01).abs() < 0.05);
+        assert!((levels[1].rms - 0.5).abs() < 1e-6);
+        assert_eq!(levels[1].peak, 0.5);
+
+        let silent = channel_levels(&[0.0; 64], 1);
+        assert_eq!(to_db(silent[0].rms), f32::NEG_INFINITY);
+    }
+
+    #[test]
+    fn meter_bars_start_from_the_readings() {
+        let mut meters = Meters::new();
+        let readings = meters.update(&sine(1000.0, 1.0, 4800), 1);
+
+        // The first frame has nothing to smooth from, so the bar starts out empty
+        assert_eq!(readings.len(), 1);
+        assert_eq!(readings[0].level, 0.0);
+        // The peak jumps straight up
+        assert!((readings[0].peak_level - 1.0).abs() < 1e-3);
+        assert!((readings[0].peak_db).abs() < 0.01);
+    }
+}
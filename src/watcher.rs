@@ -0,0 +1,249 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::thread;
+
+use color_eyre::eyre::eyre;
+use color_eyre::{Report, Result};
+
+use notify::event::{AccessKind, AccessMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+// File systems whose files change on other machines, which inotify never hears about
+const NETWORK_FILE_SYSTEMS: [&str; 11] = [
+    "nfs",
+    "nfs4",
+    "cifs",
+    "smb3",
+    "smbfs",
+    "9p",
+    "afs",
+    "ceph",
+    "glusterfs",
+    "fuse.sshfs",
+    "fuse.rclone",
+];
+
+// A library watcher once it watches every root it could, with what went wrong on the way
+type LibrarySetup = Result<(RecommendedWatcher, Option<notify::Error>)>;
+
+// Tells about files being added, changed and removed while gigr runs. Every set of paths
+// gets a watcher of its own, so that swapping out one set can't unwatch the others
+pub struct FileWatcher {
+    // The directory open in the file selector
+    directory: RecommendedWatcher,
+    directory_path: Option<PathBuf>,
+    // Everything below the library roots. Watching every directory of a large library
+    // takes a while, so the watcher is set up on a worker thread
+    library: Option<RecommendedWatcher>,
+    library_setup: Option<Receiver<LibrarySetup>>,
+    library_roots: Vec<PathBuf>,
+    // The directories of the songs in the queue
+    queue: RecommendedWatcher,
+    queue_dirs: HashSet<PathBuf>,
+
+    // Every watcher sends its events here
+    sender: Sender<notify::Result<Event>>,
+    events: Receiver<notify::Result<Event>>,
+}
+
+impl FileWatcher {
+    // Fails when the system has no way to watch files, or ran out of them
+    pub fn new() -> Result<Self> {
+        let (sender, events) = channel();
+
+        Ok(Self {
+            directory: notify::recommended_watcher(sender.clone())?,
+            directory_path: None,
+            library: None,
+            library_setup: None,
+            library_roots: Vec::new(),
+            queue: notify::recommended_watcher(sender.clone())?,
+            queue_dirs: HashSet::new(),
+
+            sender,
+            events,
+        })
+    }
+
+    // Paths that can't be watched aren't tried again until they're replaced
+    pub fn watch_directory(&mut self, path: &Path) -> Result<()> {
+        if self.directory_path.as_deref() == Some(path) {
+            return Ok(());
+        }
+
+        if let Some(previous) = self.directory_path.replace(path.to_path_buf()) {
+            let _ = self.directory.unwatch(&previous);
+        }
+        self.directory.watch(path, RecursiveMode::NonRecursive)?;
+
+        Ok(())
+    }
+
+    // Starts watching the roots in the background, `library_error` tells how that went.
+    // Roots that don't exist are skipped, there's nothing in them to change, and so are
+    // roots on network mounts, whose changes never get reported
+    pub fn watch_library(&mut self, roots: &[PathBuf]) -> () {
+        if self.library_roots == roots {
+            return;
+        }
+        self.library_roots = roots.to_vec();
+
+        // Dropping the previous watcher, or the setup of one, stops it
+        self.library = None;
+
+        let (setup_sender, setup) = channel();
+        let sender = self.sender.clone();
+        let roots = roots.to_vec();
+
+        thread::spawn(move || {
+            let setup = notify::recommended_watcher(sender)
+                .map_err(Report::from)
+                .map(|mut watcher| {
+                    let mut error = None;
+                    for root in roots
+                        .iter()
+                        .filter(|root| root.is_dir() && !is_network_mount(root))
+                    {
+                        if let Err(root_error) = watcher.watch(root, RecursiveMode::Recursive) {
+                            error.get_or_insert(root_error);
+                        }
+                    }
+
+                    (watcher, error)
+                });
+
+            let _ = setup_sender.send(setup);
+        });
+
+        self.library_setup = Some(setup);
+    }
+
+    // What went wrong while setting up the library watcher, once it's done.
+    // Running out of inotify watches gives up on the library, so that the watches
+    // that were taken can go to the file selector and the queue instead
+    pub fn library_error(&mut self) -> Option<Report> {
+        let setup = self.library_setup.as_ref()?.try_recv().ok()?;
+        self.library_setup = None;
+
+        match setup {
+            Ok((_, Some(error))) if matches!(error.kind, notify::ErrorKind::MaxFilesWatch) => {
+                return Some(eyre!(
+                    "the library has more directories than the system lets gigr watch, \
+                     raise fs.inotify.max_user_watches or rescan with u"
+                ));
+            }
+            Ok((watcher, error)) => {
+                self.library = Some(watcher);
+                return error.map(Report::from);
+            }
+            Err(error) => return Some(error),
+        }
+    }
+
+    // Directories that were deleted can't be watched anymore and are left out
+    pub fn watch_queue(&mut self, dirs: HashSet<PathBuf>) -> () {
+        for removed in self.queue_dirs.difference(&dirs) {
+            let _ = self.queue.unwatch(removed);
+        }
+        for added in dirs.difference(&self.queue_dirs) {
+            let _ = self.queue.watch(added, RecursiveMode::NonRecursive);
+        }
+
+        self.queue_dirs = dirs;
+    }
+
+    // Paths that were created, written, renamed or removed since the last call
+    pub fn changed_paths(&mut self) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = Vec::new();
+
+        for event in self.events.try_iter().flatten() {
+            // Files being opened and read are of no interest, files done being written are
+            let relevant = match event.kind {
+                EventKind::Access(AccessKind::Close(AccessMode::Write)) => true,
+                EventKind::Access(_) => false,
+                _ => true,
+            };
+
+            if relevant {
+                paths.extend(event.paths);
+            }
+        }
+
+        paths.sort();
+        paths.dedup();
+
+        return paths;
+    }
+}
+
+// Goes by the mount table, on systems without one every path is taken to be local
+fn is_network_mount(path: &Path) -> bool {
+    let Ok(mount_info) = fs::read_to_string("/proc/self/mountinfo") else {
+        return false;
+    };
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+
+    return mount_type(&mount_info, &path)
+        .is_some_and(|fs_type| NETWORK_FILE_SYSTEMS.contains(&fs_type));
+}
+
+// The type of the file system `path` is on, according to the lines of `/proc/self/mountinfo`.
+// The mount point is the fifth field, the type comes first after the ` - ` separator.
+// Of the mount points that contain the path the deepest one wins, and of equally deep ones
+// the last, which was mounted over the others
+fn mount_type<'a>(mount_info: &'a str, path: &Path) -> Option<&'a str> {
+    return mount_info
+        .lines()
+        .filter_map(|line| {
+            let (mount, file_system) = line.split_once(" - ")?;
+            let mount_point = mount.split(' ').nth(4)?.replace("\\040", " ");
+            let fs_type = file_system.split(' ').next()?;
+
+            Some((PathBuf::from(mount_point), fs_type))
+        })
+        .filter(|(mount_point, _)| path.starts_with(mount_point))
+        .max_by_key(|(mount_point, _)| mount_point.components().count())
+        .map(|(_, fs_type)| fs_type);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOUNT_INFO: &str = "\
+22 1 259:2 / / rw,relatime shared:1 - ext4 /dev/nvme0n1p2 rw
+41 22 0:38 / /home rw,relatime shared:20 - btrfs /dev/nvme0n1p3 rw
+87 41 0:52 / /home/me/Music rw,relatime shared:45 - nfs4 nas:/music rw,vers=4.2
+88 41 0:53 / /home/me/Old\\040Music rw,relatime shared:46 - cifs //nas/old rw
+89 87 0:54 / /home/me/Music rw,relatime shared:47 - ext4 /dev/sdb1 rw
+";
+
+    #[test]
+    fn the_deepest_mount_point_wins() {
+        assert_eq!(mount_type(MOUNT_INFO, Path::new("/etc")), Some("ext4"));
+        assert_eq!(
+            mount_type(MOUNT_INFO, Path::new("/home/me/Documents")),
+            Some("btrfs")
+        );
+        assert_eq!(
+            mount_type(MOUNT_INFO, Path::new("/home/me/Old Music/a")),
+            Some("cifs")
+        );
+        // Only whole components count
+        assert_eq!(
+            mount_type(MOUNT_INFO, Path::new("/home/me/Musical")),
+            Some("btrfs")
+        );
+    }
+
+    #[test]
+    fn later_mounts_cover_earlier_ones() {
+        assert_eq!(
+            mount_type(MOUNT_INFO, Path::new("/home/me/Music/Jazz")),
+            Some("ext4")
+        );
+        assert_eq!(mount_type("", Path::new("/home")), None);
+    }
+}